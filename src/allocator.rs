@@ -0,0 +1,211 @@
+use std::fmt::Write;
+
+use crate::target::Target;
+use crate::{ErrorMsg, rasm_error};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VarSpace {
+	Zeropage,
+	Bss,
+}
+
+impl VarSpace {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Zeropage => "Zero page",
+			Self::Bss => "BSS",
+		}
+	}
+}
+
+pub struct Variable {
+	pub name: String,
+	pub space: VarSpace,
+	pub addr: u16,
+	pub size: u16,
+	pub group: Option<String>,
+}
+
+impl Variable {
+	fn end(&self) -> usize {
+		self.addr as usize + self.size as usize
+	}
+
+	fn conflicts(&self, addr: u16, size: u16, group: &Option<String>) -> bool {
+		let overlaps = (addr as usize) < self.end() && (self.addr as usize) < addr as usize + size as usize;
+		let shared = matches!((&self.group, group), (Some(a), Some(b)) if a != b);
+		overlaps && !shared
+	}
+}
+
+pub struct Allocator {
+	zp_ranges: Vec<(u16, u16)>,
+	bss_ranges: Vec<(u16, u16)>,
+	zp_custom: bool,
+	bss_custom: bool,
+
+	pub variables: Vec<Variable>,
+}
+
+impl Allocator {
+	pub fn new(target: &Target) -> Self {
+		Self{
+			zp_ranges: target.zeropage_free_ranges(),
+			bss_ranges: target.bss_free_ranges(),
+			zp_custom: false, bss_custom: false,
+			variables: vec![],
+		}
+	}
+
+	pub fn ranges(&self, space: VarSpace) -> &Vec<(u16, u16)> {
+		match space {
+			VarSpace::Zeropage => &self.zp_ranges,
+			VarSpace::Bss => &self.bss_ranges,
+		}
+	}
+
+	pub fn add_range(&mut self, space: VarSpace, start: u16, end: u16, line_num: usize) {
+		if end < start {
+			rasm_error!(line_num, "Invalid range ${:04x}-${:04x}", start, end);
+		}
+
+		if space == VarSpace::Zeropage && end > u8::MAX as u16 {
+			rasm_error!(line_num, "Zero page range ${:04x}-${:04x} extends past $00ff", start, end);
+		}
+
+		if self.variables.iter().any(|v| v.space == space) {
+			rasm_error!(line_num, "{} ranges must be declared before any {} variables are allocated", space.name(), space.name().to_lowercase());
+		}
+
+		let (ranges, custom) = match space {
+			VarSpace::Zeropage => (&mut self.zp_ranges, &mut self.zp_custom),
+			VarSpace::Bss => (&mut self.bss_ranges, &mut self.bss_custom),
+		};
+
+		if !*custom {
+			ranges.clear();
+			*custom = true;
+		}
+
+		ranges.push((start, end));
+		ranges.sort();
+	}
+
+	pub fn allocate(&mut self, name: &str, space: VarSpace, size: u16, group: Option<String>, line_num: usize) -> u16 {
+		if size == 0 {
+			rasm_error!(line_num, "Variable \"{}\" has zero size", name);
+		}
+
+		if self.variables.iter().any(|v| v.name == name) {
+			rasm_error!(line_num, "Variable \"{}\" is already allocated", name);
+		}
+
+		let mut found = None;
+		'search: for &(start, end) in self.ranges(space).iter() {
+			let mut addr = start as usize;
+			while addr + size as usize - 1 <= end as usize {
+				let conflict = self.variables.iter()
+					.filter(|v| v.space == space)
+					.find(|v| v.conflicts(addr as u16, size, &group));
+
+				match conflict {
+					Some(var) => addr = var.end(),
+					None => {
+						found = Some(addr as u16);
+						break 'search;
+					},
+				}
+			}
+		}
+
+		let addr = found.unwrap_or_else(
+			|| rasm_error!(line_num, "Out of {} space allocating {} bytes for \"{}\"", space.name().to_lowercase(), size, name)
+		);
+
+		self.variables.push(Variable{name: name.into(), space, addr, size, group});
+		addr
+	}
+
+	fn free_bytes(&self, space: VarSpace) -> usize {
+		self.ranges(space).iter().map(|&(start, end)| {
+			(start..=end).filter(|&a| !self.variables.iter().any(|v| v.space == space && v.conflicts(a, 1, &None))).count()
+		}).sum()
+	}
+
+	pub fn report(&self) -> String {
+		let mut report = String::new();
+		for space in [VarSpace::Zeropage, VarSpace::Bss] {
+			let mut vars = self.variables.iter().filter(|v| v.space == space).collect::<Vec<&Variable>>();
+			if vars.is_empty() {
+				continue;
+			}
+
+			vars.sort_by_key(|v| v.addr);
+			writeln!(report, "{} allocation:", space.name()).unwrap();
+			for var in vars {
+				let group = var.group.as_ref().map_or(String::new(), |g| format!(" [{}]", g));
+				writeln!(report, "  ${:04x}-${:04x}  {} ({} bytes){}", var.addr, var.end() - 1, var.name, var.size, group).unwrap();
+			}
+
+			writeln!(report, "  {} bytes free", self.free_bytes(space)).unwrap();
+		}
+
+		report
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn allocator() -> Allocator {
+		let mut allocator = Allocator::new(&Target::C64);
+		allocator.add_range(VarSpace::Zeropage, 0x10, 0x13, 0);
+		allocator
+	}
+
+	#[test]
+	fn allocate_within_ranges() {
+		let mut allocator = allocator();
+		allocator.add_range(VarSpace::Zeropage, 0x20, 0x21, 0);
+		assert_eq!(allocator.allocate("a", VarSpace::Zeropage, 2, None, 0), 0x10);
+		assert_eq!(allocator.allocate("b", VarSpace::Zeropage, 1, None, 0), 0x12);
+		assert_eq!(allocator.allocate("c", VarSpace::Zeropage, 2, None, 0), 0x20);
+		assert_eq!(allocator.allocate("d", VarSpace::Zeropage, 1, None, 0), 0x13);
+		assert_eq!(allocator.free_bytes(VarSpace::Zeropage), 0);
+	}
+
+	#[test]
+	#[should_panic]
+	fn fail_when_out_of_space() {
+		let mut allocator = allocator();
+		allocator.allocate("a", VarSpace::Zeropage, 3, None, 0);
+		allocator.allocate("b", VarSpace::Zeropage, 2, None, 0);
+	}
+
+	#[test]
+	fn share_slots_between_groups() {
+		let mut allocator = allocator();
+		assert_eq!(allocator.allocate("a", VarSpace::Zeropage, 2, Some("irq".into()), 0), 0x10);
+		assert_eq!(allocator.allocate("b", VarSpace::Zeropage, 3, Some("main".into()), 0), 0x10);
+		assert_eq!(allocator.allocate("c", VarSpace::Zeropage, 2, Some("irq".into()), 0), 0x12);
+		assert_eq!(allocator.allocate("d", VarSpace::Zeropage, 1, Some("main".into()), 0), 0x13);
+	}
+
+	#[test]
+	#[should_panic]
+	fn fail_when_shared_slots_overlap_ungrouped() {
+		let mut allocator = allocator();
+		allocator.allocate("a", VarSpace::Zeropage, 2, Some("irq".into()), 0);
+		allocator.allocate("b", VarSpace::Zeropage, 4, Some("main".into()), 0);
+		allocator.allocate("c", VarSpace::Zeropage, 1, None, 0);
+	}
+
+	#[test]
+	#[should_panic]
+	fn reject_range_after_allocation() {
+		let mut allocator = Allocator::new(&Target::C64);
+		allocator.allocate("a", VarSpace::Zeropage, 1, None, 0);
+		allocator.add_range(VarSpace::Zeropage, 0x10, 0x13, 0);
+	}
+}
@@ -0,0 +1,337 @@
+use std::fmt::Write;
+use std::fs;
+
+use crate::utility::*;
+use crate::{ErrorMsg, rasm_error};
+
+const SYS_TOKEN: u8 = 0x9e;
+const PRINT_TOKEN: u8 = 0x99;
+const REM_TOKEN: u8 = 0x8f;
+const DATA_TOKEN: u8 = 0x83;
+const PI: u8 = 0xff;
+const FUNCTION_PREFIX: u8 = 0xce;
+const STATEMENT_PREFIX: u8 = 0xfe;
+const MAX_LINE: usize = 63999;
+const LABEL_WIDTH: usize = 5;
+
+pub const UPSTART_LINE: u16 = 10;
+pub const UPSTART_SIZE: usize = 13;
+
+const V2_TOKENS: [&str; 76] = [
+	"end", "for", "next", "data", "input#", "input", "dim", "read", "let", "goto", "run", "if", "restore", "gosub", "return", "rem",
+	"stop", "on", "wait", "load", "save", "verify", "def", "poke", "print#", "print", "cont", "list", "clr", "cmd", "sys", "open",
+	"close", "get", "new", "tab(", "to", "fn", "spc(", "then", "not", "step", "+", "-", "*", "/", "^", "and",
+	"or", ">", "=", "<", "sgn", "int", "abs", "usr", "fre", "pos", "sqr", "rnd", "log", "exp", "cos", "sin",
+	"tan", "atn", "peek", "len", "str$", "val", "asc", "chr$", "left$", "right$", "mid$", "go",
+];
+
+const V35_TOKENS: [&str; 50] = [
+	"rgr", "rclr", "rlum", "joy", "rdot", "dec", "hex$", "err$", "instr", "else", "resume", "trap", "tron", "troff", "sound", "vol",
+	"auto", "pudef", "graphic", "paint", "char", "box", "circle", "gshape", "sshape", "draw", "locate", "color", "scnclr", "scale", "help", "do",
+	"loop", "exit", "directory", "dsave", "dload", "header", "scratch", "collect", "copy", "rename", "backup", "delete", "renumber", "key", "monitor", "using",
+	"until", "while",
+];
+
+const V70_FUNCTIONS: [&str; 9] = ["pot", "bump", "pen", "rsppos", "rsprite", "rspcolor", "xor", "rwindow", "pointer"];
+
+const V70_STATEMENTS: [&str; 37] = [
+	"bank", "filter", "play", "tempo", "movspr", "sprite", "sprcolor", "rreg", "envelope", "sleep", "catalog", "dopen", "append", "dclose", "bsave", "bload",
+	"record", "concat", "dverify", "dclear", "sprsav", "collision", "begin", "bend", "window", "boot", "width", "sprdef", "quit", "stash", "", "fetch",
+	"", "swap", "off", "fast", "slow",
+];
+
+const CONTROL_CODES: [(&str, u8); 40] = [
+	("clr", 0x93), ("clear", 0x93), ("home", 0x13), ("del", 0x14), ("inst", 0x94), ("rvs on", 0x12), ("rvon", 0x12), ("rvs off", 0x92),
+	("rvof", 0x92), ("down", 0x11), ("up", 0x91), ("left", 0x9d), ("rght", 0x1d), ("right", 0x1d), ("wht", 0x05), ("red", 0x1c),
+	("grn", 0x1e), ("blu", 0x1f), ("blk", 0x90), ("pur", 0x9c), ("yel", 0x9e), ("cyn", 0x9f), ("orng", 0x81), ("brn", 0x95),
+	("lred", 0x96), ("gry1", 0x97), ("gry2", 0x98), ("lgrn", 0x99), ("lblu", 0x9a), ("gry3", 0x9b), ("f1", 0x85), ("f3", 0x86),
+	("f5", 0x87), ("f7", 0x88), ("f2", 0x89), ("f4", 0x8a), ("f6", 0x8b), ("f8", 0x8c),
+	("return", 0x0d), ("pi", PI),
+];
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum BasicVersion {
+	V2,
+	V35,
+	V70,
+}
+
+impl BasicVersion {
+	pub fn from_string(string: &str) -> Self {
+		match string.to_lowercase().trim_start_matches('v') {
+			"2" | "2.0" => Self::V2,
+			"3.5" => Self::V35,
+			"7" | "7.0" => Self::V70,
+			_ => rasm_error!(0, "Unknown BASIC version \"{}\"", string),
+		}
+	}
+
+	fn tokens(&self) -> Vec<(&'static str, Vec<u8>)> {
+		let mut tokens = V2_TOKENS.iter().enumerate().map(|(i, t)| (*t, vec![0x80 + i as u8])).collect::<Vec<(&str, Vec<u8>)>>();
+		if *self >= Self::V35 {
+			tokens.extend(V35_TOKENS.iter().enumerate().map(|(i, t)| (*t, vec![0xcc + i as u8])));
+		}
+
+		if *self == Self::V70 {
+			tokens.retain(|t| t.1 != [FUNCTION_PREFIX]);
+			tokens.extend(V70_FUNCTIONS.iter().enumerate().map(|(i, t)| (*t, vec![FUNCTION_PREFIX, 0x02 + i as u8])));
+			tokens.extend(V70_STATEMENTS.iter().enumerate().map(|(i, t)| (*t, vec![STATEMENT_PREFIX, 0x02 + i as u8])));
+			tokens.retain(|t| !t.0.is_empty());
+		}
+
+		tokens
+	}
+}
+
+pub fn upstart_stub(basic_start: u16, addr: u16) -> Vec<u8> {
+	let next_line = basic_start + UPSTART_SIZE as u16 - 2;
+	let mut stub = vec![lo8(next_line), hi8(next_line), lo8(UPSTART_LINE), hi8(UPSTART_LINE), SYS_TOKEN];
+	stub.extend(format!("{:05}", addr).bytes());
+	stub.extend_from_slice(&[0, 0, 0]);
+	stub
+}
+
+fn petscii(c: char, quoted: bool, line_num: usize, number: usize) -> u8 {
+	match c {
+		'a'..='z' => c as u8 - 0x20,
+		'A'..='Z' if quoted => c as u8 + 0x80,
+		'A'..='Z' => c as u8,
+		' '..='_' => c as u8,
+		_ => rasm_error!(line_num, "Character '{}' cannot be used in BASIC line {}", c, number),
+	}
+}
+
+// Control code names win over symbols, so a label called e.g. "home" or "red" cannot be used inside braces
+fn brace_code(code: &str, line_num: usize, number: usize, resolve: &mut dyn FnMut(&str) -> Option<u16>) -> Vec<u8> {
+	let lower = code.to_lowercase();
+	let (count, name) = match lower.split_once(' ') {
+		Some((count, name)) if count.parse::<usize>().is_ok() => (count.parse::<usize>().unwrap(), name.to_string()),
+		_ => (1, lower.clone()),
+	};
+
+	if let Some((_, byte)) = CONTROL_CODES.iter().find(|c| c.0 == name) {
+		return vec![*byte; count];
+	}
+
+	if let Some(hex) = name.strip_prefix('$') {
+		let byte = u8::from_str_radix(hex, 16).unwrap_or_else(|_| rasm_error!(line_num, "Invalid control code \"{{{}}}\" in BASIC line {}", code, number));
+		return vec![byte; count];
+	}
+
+	match resolve(code) {
+		Some(value) => format!("{:>width$}", value, width = LABEL_WIDTH).into_bytes(),
+		None => rasm_error!(line_num, "Unknown control code or symbol \"{{{}}}\" in BASIC line {}", code, number),
+	}
+}
+
+fn tokenize_line(text: &str, tokens: &[(&str, Vec<u8>)], line_num: usize, number: usize, resolve: &mut dyn FnMut(&str) -> Option<u16>) -> Vec<u8> {
+	let mut bytes = vec![];
+	let (mut quoted, mut data, mut rem) = (false, false, false);
+	let mut rest = text;
+	while let Some(c) = rest.chars().next() {
+		if c == '{' {
+			let end = rest.find('}').unwrap_or_else(|| rasm_error!(line_num, "Unterminated control code in BASIC line {}", number));
+			bytes.extend(brace_code(&rest[1..end], line_num, number, resolve));
+			rest = &rest[end + 1..];
+			continue;
+		}
+
+		if !quoted && !data && !rem {
+			let lower = rest.to_lowercase();
+			let token = tokens.iter().filter(|t| lower.starts_with(t.0)).max_by_key(|t| t.0.len());
+			if let Some((name, token)) = token {
+				bytes.extend(token);
+				rem = token == &[REM_TOKEN];
+				data = token == &[DATA_TOKEN];
+				rest = &rest[name.len()..];
+				continue;
+			}
+
+			if c == '?' {
+				bytes.push(PRINT_TOKEN);
+				rest = &rest[1..];
+				continue;
+			}
+		}
+
+		if c == '"' {
+			quoted = !quoted;
+		} else if c == ':' && !quoted {
+			data = false;
+		}
+
+		bytes.push(petscii(c, quoted || rem, line_num, number));
+		rest = &rest[c.len_utf8()..];
+	}
+
+	bytes
+}
+
+pub fn tokenize(source: &str, start: u16, version: BasicVersion, line_num: usize, resolve: &mut dyn FnMut(&str) -> Option<u16>) -> Vec<u8> {
+	let tokens = version.tokens();
+	let mut program = vec![];
+	let mut last_line = None;
+
+	for line in source.lines().filter(|l| !l.trim().is_empty()) {
+		let line = line.trim_start();
+		let digits = line.find(|c: char| !c.is_ascii_digit()).unwrap_or(line.len());
+		let number = line[..digits].parse::<usize>().ok().filter(|n| *n <= MAX_LINE).unwrap_or_else(
+			|| rasm_error!(line_num, "Invalid BASIC line number in \"{}\"", line)
+		);
+
+		if last_line.is_some_and(|l| number <= l) {
+			rasm_error!(line_num, "BASIC line {} is not in ascending order", number);
+		}
+
+		last_line = Some(number);
+		let body = tokenize_line(line[digits..].trim_start(), &tokens, line_num, number, resolve);
+		let next = start as usize + program.len() + 4 + body.len() + 1;
+		program.extend_from_slice(&[(next & 0xff) as u8, (next >> 8) as u8, (number & 0xff) as u8, (number >> 8) as u8]);
+		program.extend(body);
+		program.push(0);
+	}
+
+	program.extend_from_slice(&[0, 0]);
+	program
+}
+
+fn detokenize_byte(byte: u8, quoted: bool) -> String {
+	match byte {
+		0x41..=0x5a => ((byte + 0x20) as char).to_string(),
+		0xc1..=0xda if quoted => ((byte - 0x80) as char).to_string(),
+		0x20..=0x5f => (byte as char).to_string(),
+		_ => match CONTROL_CODES.iter().find(|c| c.1 == byte) {
+			Some((name, _)) => format!("{{{}}}", name),
+			None => format!("{{${:02x}}}", byte),
+		},
+	}
+}
+
+pub fn detokenize(prg: &[u8], version: BasicVersion) -> String {
+	let tokens = version.tokens();
+	if prg.len() < 2 {
+		rasm_error!(0, "{}", "File is too short to be a PRG");
+	}
+
+	let mut listing = String::new();
+	let mut pos = 2;
+	while pos + 4 <= prg.len() {
+		let next = prg[pos] as usize | (prg[pos + 1] as usize) << 8;
+		if next == 0 {
+			break;
+		}
+
+		let number = prg[pos + 2] as usize | (prg[pos + 3] as usize) << 8;
+		write!(listing, "{} ", number).unwrap();
+		pos += 4;
+
+		let (mut quoted, mut rem) = (false, false);
+		while pos < prg.len() && prg[pos] != 0 {
+			let byte = prg[pos];
+			pos += 1;
+
+			if byte == b'"' {
+				quoted = !quoted;
+			}
+
+			if quoted || rem || byte < 0x80 || byte == PI {
+				listing.push_str(&detokenize_byte(byte, quoted || rem));
+				continue;
+			}
+
+			let code = if version == BasicVersion::V70 && (byte == FUNCTION_PREFIX || byte == STATEMENT_PREFIX) && pos < prg.len() {
+				pos += 1;
+				vec![byte, prg[pos - 1]]
+			} else {
+				vec![byte]
+			};
+
+			match tokens.iter().find(|t| t.1 == code) {
+				Some((name, _)) => listing.push_str(name),
+				None => code.iter().for_each(|b| listing.push_str(&format!("{{${:02x}}}", b))),
+			}
+
+			rem = code == [REM_TOKEN];
+		}
+
+		listing.push('\n');
+		pos += 1;
+	}
+
+	listing
+}
+
+pub fn detokenize_command(args: Vec<String>) {
+	let path = args.first().unwrap_or_else(|| rasm_error!(0, "{}", "No PRG file specified"));
+	let version = args.get(1).map_or(BasicVersion::V2, |v| BasicVersion::from_string(v));
+	let prg = fs::read(path).unwrap_or_else(|_| rasm_error!(0, "Failed to open PRG file {}", path));
+	print!("{}", detokenize(&prg, version));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn resolve(name: &str) -> Option<u16> {
+		match name {
+			"start" => Some(2061),
+			"home" => Some(0xc000),
+			_ => None,
+		}
+	}
+
+	fn line_body(text: &str, version: BasicVersion) -> Vec<u8> {
+		let program = tokenize(&format!("10 {}", text), 0x0801, version, 0, &mut resolve);
+		program[4..program.len() - 3].to_vec()
+	}
+
+	#[test]
+	fn tokens_from_each_table() {
+		assert_eq!(line_body("print", BasicVersion::V2), vec![0x99]);
+		assert_eq!(line_body("go to", BasicVersion::V2), vec![0xcb, b' ', 0xa4]);
+		assert_eq!(line_body("scnclr", BasicVersion::V35), vec![0xe8]);
+		assert_eq!(line_body("pointer", BasicVersion::V70), vec![0xce, 0x0a]);
+		assert_eq!(line_body("fetch", BasicVersion::V70), vec![0xfe, 0x21]);
+		assert_eq!(line_body("scnclr", BasicVersion::V2), vec![b'S', b'C', b'N', 0x9c]);
+	}
+
+	#[test]
+	fn line_links_and_numbers() {
+		let program = tokenize("10 print\n20 end\n", 0x0801, BasicVersion::V2, 0, &mut resolve);
+		assert_eq!(program, vec![0x07, 0x08, 10, 0, 0x99, 0, 0x0d, 0x08, 20, 0, 0x80, 0, 0, 0]);
+	}
+
+	#[test]
+	fn control_codes_and_symbols() {
+		assert_eq!(line_body("?\"{clr}{3 down}\"", BasicVersion::V2), vec![0x99, b'"', 0x93, 0x11, 0x11, 0x11, b'"']);
+		assert_eq!(line_body("sys{start}", BasicVersion::V2), vec![0x9e, b' ', b'2', b'0', b'6', b'1']);
+		assert_eq!(line_body("?\"{home}\"", BasicVersion::V2), vec![0x99, b'"', 0x13, b'"']);
+	}
+
+	#[test]
+	fn shifted_letters_in_quotes_and_rem() {
+		assert_eq!(line_body("?\"Ab\"", BasicVersion::V2), vec![0x99, b'"', 0xc1, b'B', b'"']);
+		assert_eq!(line_body("rem Hi print", BasicVersion::V2), vec![0x8f, b' ', 0xc8, b'I', b' ', b'P', b'R', b'I', b'N', b'T']);
+	}
+
+	#[test]
+	fn data_is_not_tokenized() {
+		assert_eq!(line_body("data to,go:end", BasicVersion::V2), vec![0x83, b' ', b'T', b'O', b',', b'G', b'O', b':', 0x80]);
+	}
+
+	#[test]
+	fn round_trip() {
+		let samples = [
+			("10 print \"{clr}hello{3 down}\"\n20 goto 10\n", BasicVersion::V2),
+			("10 rem Mixed Case\n20 data 1,2,to\n30 poke 53280,peek(53281)\n", BasicVersion::V2),
+			("10 scnclr:color 0,1\n20 do:loop until joy(1)\n", BasicVersion::V35),
+			("10 fast:sprite 1,1\n20 x=pointer(a):quit\n", BasicVersion::V70),
+		];
+
+		for (source, version) in samples {
+			let mut prg = vec![0x01, 0x08];
+			prg.extend(tokenize(source, 0x0801, version, 0, &mut resolve));
+			assert_eq!(detokenize(&prg, version), source.replace("{3 down}", "{down}{down}{down}"));
+		}
+	}
+}
@@ -0,0 +1,168 @@
+use crate::output::Chunk;
+use crate::{ErrorMsg, rasm_error};
+
+const CRT_SIGNATURE: &[u8] = b"C64 CARTRIDGE   ";
+pub const CBM80_SIGNATURE: [u8; 5] = [0xc3, 0xc2, 0xcd, 0x38, 0x30];
+pub const CBM80_ADDR: u16 = 0x8000;
+pub const BANK_ORIGIN: u16 = 0x8000;
+
+const ROML: u16 = 0x8000;
+const ROMH: u16 = 0xa000;
+const ROMH_ULTIMAX: u16 = 0xe000;
+const CHIP_SIZE: usize = 0x2000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CartType {
+	Normal8k,
+	Normal16k,
+	Ultimax,
+	Ocean,
+	MagicDesk,
+	EasyFlash,
+}
+
+impl CartType {
+	pub fn from_string(string: &str) -> Self {
+		match string.to_lowercase().as_str() {
+			"8k" => Self::Normal8k,
+			"16k" => Self::Normal16k,
+			"ultimax" => Self::Ultimax,
+			"ocean" => Self::Ocean,
+			"magicdesk" => Self::MagicDesk,
+			"easyflash" => Self::EasyFlash,
+			_ => rasm_error!(0, "Unknown cartridge type \"{}\"", string),
+		}
+	}
+
+	fn hardware(&self) -> u16 {
+		match self {
+			Self::Normal8k | Self::Normal16k | Self::Ultimax => 0,
+			Self::Ocean => 5,
+			Self::MagicDesk => 19,
+			Self::EasyFlash => 32,
+		}
+	}
+
+	fn lines(&self) -> (u8, u8) {
+		match self {
+			Self::Normal8k | Self::MagicDesk => (0, 1),
+			Self::Normal16k | Self::Ocean => (0, 0),
+			Self::Ultimax | Self::EasyFlash => (1, 0),
+		}
+	}
+
+	fn chips(&self) -> Vec<u16> {
+		match self {
+			Self::Normal8k | Self::MagicDesk => vec![ROML],
+			Self::Normal16k | Self::Ocean | Self::EasyFlash => vec![ROML, ROMH],
+			Self::Ultimax => vec![ROML, ROMH_ULTIMAX],
+		}
+	}
+
+	fn max_banks(&self) -> usize {
+		match self {
+			Self::Normal8k | Self::Normal16k | Self::Ultimax => 1,
+			Self::Ocean | Self::EasyFlash => 64,
+			Self::MagicDesk => 128,
+		}
+	}
+
+	pub fn is_banked(&self) -> bool {
+		self.max_banks() > 1
+	}
+}
+
+fn chip_name(addr: u16) -> &'static str {
+	if addr == ROML { "ROML" } else { "ROMH" }
+}
+
+pub fn crt_header(hardware: u16, exrom: u8, game: u8, name: &str) -> Vec<u8> {
+	let mut header = CRT_SIGNATURE.to_vec();
+	header.extend_from_slice(&0x40u32.to_be_bytes());
+	header.extend_from_slice(&0x0100u16.to_be_bytes());
+	header.extend_from_slice(&hardware.to_be_bytes());
+	header.extend_from_slice(&[exrom, game, 0, 0, 0, 0, 0, 0]);
+
+	let mut name = name.to_uppercase().bytes().take(32).collect::<Vec<u8>>();
+	name.resize(32, 0);
+	header.extend(name);
+	header
+}
+
+pub fn chip_packet(bank: u16, addr: u16, data: &[u8]) -> Vec<u8> {
+	let mut packet = b"CHIP".to_vec();
+	packet.extend_from_slice(&(0x10 + data.len() as u32).to_be_bytes());
+	packet.extend_from_slice(&[0, 0]);
+	packet.extend_from_slice(&bank.to_be_bytes());
+	packet.extend_from_slice(&addr.to_be_bytes());
+	packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+	packet.extend_from_slice(data);
+	packet
+}
+
+pub fn chip_images(chunks: &[&Chunk], chips: &[u16], fill: u8) -> Vec<Option<Vec<u8>>> {
+	let mut images = vec![None; chips.len()];
+	for chunk in chunks.iter().filter(|c| !c.bytes.is_empty()) {
+		for (offset, byte) in chunk.bytes.iter().enumerate() {
+			let addr = chunk.start as usize + offset;
+			let index = chips.iter().position(|c| addr >= *c as usize && addr < *c as usize + CHIP_SIZE).unwrap_or_else(|| {
+				let windows = chips.iter().map(|c| format!("{} ${:04x}-${:04x}", chip_name(*c), c, *c as usize + CHIP_SIZE - 1));
+				rasm_error!(chunk.line_num, "Byte at ${:04x} does not fit in the cartridge ({})", addr, windows.collect::<Vec<String>>().join(", "));
+			});
+
+			let image = images[index].get_or_insert_with(|| vec![fill; CHIP_SIZE]);
+			image[addr - chips[index] as usize] = *byte;
+		}
+	}
+
+	images
+}
+
+fn write_banks(image: &mut Vec<u8>, chunks: &[Chunk], cart: CartType, fill: u8) {
+	let chunks = chunks.iter().filter(|c| !c.bytes.is_empty()).collect::<Vec<&Chunk>>();
+	let mut banks = chunks.iter().map(|c| c.bank.unwrap_or_else(
+		|| rasm_error!(c.line_num, "{}", "Code outside a .bank block cannot be placed in a banked cartridge")
+	)).collect::<Vec<usize>>();
+
+	banks.sort();
+	banks.dedup();
+
+	let chips = cart.chips();
+	for bank in banks {
+		let bank_chunks = chunks.iter().filter(|c| c.bank == Some(bank)).copied().collect::<Vec<&Chunk>>();
+		if bank >= cart.max_banks() {
+			rasm_error!(bank_chunks[0].line_num, "Bank {} is out of range; this cartridge type has {} banks", bank, cart.max_banks());
+		}
+
+		for (chip, data) in chips.iter().zip(chip_images(&bank_chunks, &chips, fill)) {
+			if let Some(data) = data {
+				image.extend(chip_packet(bank as u16, *chip, &data));
+			}
+		}
+	}
+}
+
+pub fn write_crt(chunks: &[Chunk], cart: CartType, name: &str, fill: u8) -> Vec<u8> {
+	let (exrom, game) = cart.lines();
+	let mut image = crt_header(cart.hardware(), exrom, game, name);
+	if cart.is_banked() {
+		write_banks(&mut image, chunks, cart, fill);
+		return image;
+	}
+
+	if let Some(chunk) = chunks.iter().find(|c| !c.bytes.is_empty() && c.bank.is_some()) {
+		rasm_error!(chunk.line_num, "{}", "Banks require a banked cartridge type (easyflash, magicdesk or ocean)");
+	}
+
+	let chips = cart.chips();
+	let chunks = chunks.iter().collect::<Vec<&Chunk>>();
+	for (chip, data) in chips.iter().zip(chip_images(&chunks, &chips, fill)) {
+		match (data, cart) {
+			(Some(data), _) => image.extend(chip_packet(0, *chip, &data)),
+			(None, CartType::Ultimax) if *chip == ROML => {},
+			(None, _) => image.extend(chip_packet(0, *chip, &vec![fill; CHIP_SIZE])),
+		}
+	}
+
+	image
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use crate::basic;
+use crate::utility::*;
+use crate::{ErrorMsg, rasm_error};
+
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+const MAX_LITERALS: usize = 0x7f;
+const MAX_OFFSET: usize = 0xffff;
+const MAX_CANDIDATES: usize = 64;
+
+const STUB_ADDR: u16 = 0x0801;
+const MOVER_ADDR: u16 = STUB_ADDR + basic::UPSTART_SIZE as u16;
+const MOVER_SIZE: usize = 71;
+const DECRUNCH_ADDR: u16 = 0x0334;
+const DECRUNCH_SIZE: usize = 93;
+const PACKED_TOP: usize = 0xff00;
+
+const SRC: u8 = 0xfb;
+const DST: u8 = 0xfd;
+const REF: u8 = 0xf7;
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+	let mut packed = vec![];
+	let mut literals = vec![];
+	let mut table = HashMap::<&[u8], Vec<usize>>::new();
+
+	let mut pos = 0;
+	while pos < data.len() {
+		let mut best = (0, 0);
+		if pos + MIN_MATCH <= data.len() {
+			let key = &data[pos..pos + MIN_MATCH];
+			if let Some(candidates) = table.get(key) {
+				for start in candidates.iter().rev().take(MAX_CANDIDATES) {
+					if pos - start > MAX_OFFSET {
+						break;
+					}
+
+					let length = data[pos..].iter().zip(&data[*start..]).take(MAX_MATCH).take_while(|(a, b)| a == b).count();
+					if length > best.0 {
+						best = (length, pos - start);
+					}
+				}
+			}
+		}
+
+		let step = if best.0 >= MIN_MATCH { best.0 } else { 1 };
+		for index in pos..(pos + step).min(data.len().saturating_sub(MIN_MATCH - 1)) {
+			table.entry(&data[index..index + MIN_MATCH]).or_default().push(index);
+		}
+
+		if best.0 >= MIN_MATCH {
+			flush_literals(&mut packed, &mut literals);
+			packed.extend_from_slice(&[0x80 | (best.0 - MIN_MATCH) as u8, (best.1 & 0xff) as u8, (best.1 >> 8) as u8]);
+		} else {
+			literals.push(data[pos]);
+			if literals.len() == MAX_LITERALS {
+				flush_literals(&mut packed, &mut literals);
+			}
+		}
+
+		pos += step;
+	}
+
+	flush_literals(&mut packed, &mut literals);
+	packed.push(0);
+	packed
+}
+
+fn flush_literals(packed: &mut Vec<u8>, literals: &mut Vec<u8>) {
+	if !literals.is_empty() {
+		packed.push(literals.len() as u8);
+		packed.append(literals);
+	}
+}
+
+fn set_pointer(code: &mut Vec<u8>, zp: u8, addr: u16) {
+	code.extend_from_slice(&[0xa9, lo8(addr), 0x85, zp, 0xa9, hi8(addr), 0x85, zp + 1]);
+}
+
+fn decruncher(entry: u16) -> Vec<u8> {
+	let inc_src = DECRUNCH_ADDR + 79;
+	let inc_dst = DECRUNCH_ADDR + 86;
+	vec![
+		0xa0, 0x00, 0xb1, SRC, 0xf0, 0x41, 0xaa, 0x20, lo8(inc_src), hi8(inc_src), 0x8a, 0x30, 0x0f,
+		0xb1, SRC, 0x91, DST, 0x20, lo8(inc_src), hi8(inc_src), 0x20, lo8(inc_dst), hi8(inc_dst), 0xca, 0xd0, 0xf3, 0xf0, 0xe4,
+		0x29, 0x7f, 0x18, 0x69, MIN_MATCH as u8, 0xaa, 0xa5, DST, 0x38, 0xf1, SRC, 0x85, REF, 0x20, lo8(inc_src), hi8(inc_src),
+		0xa5, DST + 1, 0xf1, SRC, 0x85, REF + 1, 0x20, lo8(inc_src), hi8(inc_src),
+		0xb1, REF, 0x91, DST, 0xe6, REF, 0xd0, 0x02, 0xe6, REF + 1, 0x20, lo8(inc_dst), hi8(inc_dst), 0xca, 0xd0, 0xf0, 0xf0, 0xb9,
+		0xa9, 0x37, 0x85, 0x01, 0x58, 0x4c, lo8(entry), hi8(entry),
+		0xe6, SRC, 0xd0, 0x02, 0xe6, SRC + 1, 0x60,
+		0xe6, DST, 0xd0, 0x02, 0xe6, DST + 1, 0x60,
+	]
+}
+
+fn mover(load_addr: u16, packed_src: u16, packed_dst: u16, pages: u8) -> Vec<u8> {
+	let decruncher_src = MOVER_ADDR + MOVER_SIZE as u16;
+	let last_page = (pages as u16 - 1) << 8;
+
+	let mut code = vec![0x78, 0xa9, 0x34, 0x85, 0x01, 0xa2, DECRUNCH_SIZE as u8];
+	code.extend_from_slice(&[0xbd, lo8(decruncher_src - 1), hi8(decruncher_src - 1)]);
+	code.extend_from_slice(&[0x9d, lo8(DECRUNCH_ADDR - 1), hi8(DECRUNCH_ADDR - 1), 0xca, 0xd0, 0xf7]);
+	set_pointer(&mut code, SRC, packed_src + last_page);
+	set_pointer(&mut code, DST, packed_dst + last_page);
+	code.extend_from_slice(&[0xa2, pages, 0xa0, 0x00, 0x88, 0xb1, SRC, 0x91, DST, 0xc0, 0x00, 0xd0, 0xf7]);
+	code.extend_from_slice(&[0xc6, SRC + 1, 0xc6, DST + 1, 0xca, 0xd0, 0xf0]);
+	set_pointer(&mut code, SRC, packed_dst);
+	set_pointer(&mut code, DST, load_addr);
+	code.extend_from_slice(&[0x4c, lo8(DECRUNCH_ADDR), hi8(DECRUNCH_ADDR)]);
+	code
+}
+
+pub fn crunch_prg(prg: &[u8], entry: u16) -> Vec<u8> {
+	let load_addr = prg[0] as u16 | (prg[1] as u16) << 8;
+	let data = &prg[2..];
+	let packed = compress(data);
+
+	let pages = packed.len().div_ceil(0x100);
+	let packed_src = MOVER_ADDR as usize + MOVER_SIZE + DECRUNCH_SIZE;
+	let packed_dst = PACKED_TOP - pages * 0x100;
+	let load_end = load_addr as usize + data.len();
+
+	if packed_dst < packed_src || load_end > packed_dst {
+		rasm_error!(0, "Program at ${:04x}-${:04x} is too large to be crunched", load_addr, load_end - 1);
+	}
+
+	if (load_addr as usize) < DECRUNCH_ADDR as usize + DECRUNCH_SIZE && load_end > DECRUNCH_ADDR as usize {
+		rasm_error!(0, "Program at ${:04x}-${:04x} overlaps the decruncher at ${:04x}", load_addr, load_end - 1, DECRUNCH_ADDR);
+	}
+
+	let mut code = vec![lo8(STUB_ADDR), hi8(STUB_ADDR)];
+	code.extend(basic::upstart_stub(STUB_ADDR, MOVER_ADDR));
+	code.extend(mover(load_addr, packed_src as u16, packed_dst as u16, pages as u8));
+	code.extend(decruncher(entry));
+	code.extend(&packed);
+
+	println!("Crunched {} bytes to {} bytes ({:.1}%)", data.len(), code.len() - 2, (code.len() - 2) as f64 * 100.0 / data.len().max(1) as f64);
+	code
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn decompress(packed: &[u8]) -> Vec<u8> {
+		let mut data = vec![];
+		let mut pos = 0;
+		loop {
+			let control = packed[pos] as usize;
+			pos += 1;
+
+			if control == 0 {
+				return data;
+			} else if control & 0x80 == 0 {
+				data.extend_from_slice(&packed[pos..pos + control]);
+				pos += control;
+			} else {
+				let offset = packed[pos] as usize | (packed[pos + 1] as usize) << 8;
+				pos += 2;
+				for _ in 0..(control & 0x7f) + MIN_MATCH {
+					data.push(data[data.len() - offset]);
+				}
+			}
+		}
+	}
+
+	// Just enough of a 6502 to run the mover and decruncher
+	struct Cpu {
+		memory: Vec<u8>,
+		pc: u16,
+		a: u8,
+		x: u8,
+		y: u8,
+		sp: u8,
+		n: bool,
+		z: bool,
+		c: bool,
+	}
+
+	impl Cpu {
+		fn fetch(&mut self) -> u8 {
+			let byte = self.memory[self.pc as usize];
+			self.pc = self.pc.wrapping_add(1);
+			byte
+		}
+
+		fn fetch_word(&mut self) -> u16 {
+			self.fetch() as u16 | (self.fetch() as u16) << 8
+		}
+
+		fn pointer(&self, zp: u8) -> u16 {
+			self.memory[zp as usize] as u16 | (self.memory[zp.wrapping_add(1) as usize] as u16) << 8
+		}
+
+		fn indirect_y(&mut self) -> usize {
+			let zp = self.fetch();
+			self.pointer(zp).wrapping_add(self.y as u16) as usize
+		}
+
+		fn set_nz(&mut self, value: u8) -> u8 {
+			self.n = value & 0x80 != 0;
+			self.z = value == 0;
+			value
+		}
+
+		fn branch(&mut self, taken: bool) {
+			let offset = self.fetch() as i8;
+			if taken {
+				self.pc = self.pc.wrapping_add(offset as u16);
+			}
+		}
+
+		fn push(&mut self, value: u8) {
+			self.memory[0x100 + self.sp as usize] = value;
+			self.sp = self.sp.wrapping_sub(1);
+		}
+
+		fn pull(&mut self) -> u8 {
+			self.sp = self.sp.wrapping_add(1);
+			self.memory[0x100 + self.sp as usize]
+		}
+
+		fn step(&mut self) {
+			let opcode = self.fetch();
+			match opcode {
+				0x18 => self.c = false,
+				0x38 => self.c = true,
+				0x58 | 0x78 => {},
+				0xa9 => { let value = self.fetch(); self.a = self.set_nz(value); },
+				0xa2 => { let value = self.fetch(); self.x = self.set_nz(value); },
+				0xa0 => { let value = self.fetch(); self.y = self.set_nz(value); },
+				0xa5 => { let zp = self.fetch(); self.a = self.set_nz(self.memory[zp as usize]); },
+				0x85 => { let zp = self.fetch(); self.memory[zp as usize] = self.a; },
+				0xbd => { let addr = self.fetch_word().wrapping_add(self.x as u16); self.a = self.set_nz(self.memory[addr as usize]); },
+				0x9d => { let addr = self.fetch_word().wrapping_add(self.x as u16); self.memory[addr as usize] = self.a; },
+				0xb1 => { let addr = self.indirect_y(); self.a = self.set_nz(self.memory[addr]); },
+				0x91 => { let addr = self.indirect_y(); self.memory[addr] = self.a; },
+				0xaa => self.x = self.set_nz(self.a),
+				0x8a => self.a = self.set_nz(self.x),
+				0xca => self.x = self.set_nz(self.x.wrapping_sub(1)),
+				0x88 => self.y = self.set_nz(self.y.wrapping_sub(1)),
+				0xe6 => { let zp = self.fetch() as usize; self.memory[zp] = self.set_nz(self.memory[zp].wrapping_add(1)); },
+				0xc6 => { let zp = self.fetch() as usize; self.memory[zp] = self.set_nz(self.memory[zp].wrapping_sub(1)); },
+				0x29 => { let value = self.fetch(); self.a = self.set_nz(self.a & value); },
+				0x69 => {
+					let sum = self.a as u16 + self.fetch() as u16 + self.c as u16;
+					self.c = sum > 0xff;
+					self.a = self.set_nz(sum as u8);
+				},
+				0xf1 => {
+					let addr = self.indirect_y();
+					let diff = self.a as i16 - self.memory[addr] as i16 - !self.c as i16;
+					self.c = diff >= 0;
+					self.a = self.set_nz(diff as u8);
+				},
+				0xc0 => { let value = self.fetch(); self.c = self.y >= value; self.set_nz(self.y.wrapping_sub(value)); },
+				0xd0 => self.branch(!self.z),
+				0xf0 => self.branch(self.z),
+				0x30 => self.branch(self.n),
+				0x4c => self.pc = self.fetch_word(),
+				0x20 => {
+					let target = self.fetch_word();
+					let ret = self.pc.wrapping_sub(1);
+					self.push(hi8(ret));
+					self.push(lo8(ret));
+					self.pc = target;
+				},
+				0x60 => {
+					let ret = self.pull() as u16 | (self.pull() as u16) << 8;
+					self.pc = ret.wrapping_add(1);
+				},
+				_ => panic!("Unsupported opcode ${:02x} at ${:04x}", opcode, self.pc.wrapping_sub(1)),
+			}
+		}
+	}
+
+	fn run_crunched(crunched: &[u8], entry: u16) -> Vec<u8> {
+		let mut memory = vec![0; 0x10000];
+		let load_addr = crunched[0] as usize | (crunched[1] as usize) << 8;
+		memory[load_addr..load_addr + crunched.len() - 2].copy_from_slice(&crunched[2..]);
+
+		let mut cpu = Cpu{memory, pc: MOVER_ADDR, a: 0, x: 0, y: 0, sp: 0xff, n: false, z: false, c: false};
+		for _ in 0..50_000_000 {
+			if cpu.pc == entry {
+				return cpu.memory;
+			}
+
+			cpu.step();
+		}
+
+		panic!("Decruncher did not reach the entry point");
+	}
+
+	fn samples() -> Vec<Vec<u8>> {
+		let mut samples = vec![vec![], vec![0x42], vec![0; 1000], (0..=255).collect::<Vec<u8>>()];
+		samples.push((0..5000).map(|i| ((i * 7) % 13 + (i / 100) % 5) as u8).collect());
+		samples.push((0..3000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect());
+		samples.push(b"abcabcabcabcabcd".repeat(40));
+		samples
+	}
+
+	#[test]
+	fn round_trip() {
+		for data in samples() {
+			let packed = compress(&data);
+			assert_eq!(decompress(&packed), data);
+		}
+	}
+
+	#[test]
+	fn decrunch_on_6502() {
+		for load_addr in [0x0801u16, 0x1000] {
+			for data in samples() {
+				let mut prg = vec![lo8(load_addr), hi8(load_addr)];
+				prg.extend(&data);
+
+				let memory = run_crunched(&crunch_prg(&prg, load_addr), load_addr);
+				assert_eq!(&memory[load_addr as usize..load_addr as usize + data.len()], &data[..]);
+			}
+		}
+	}
+
+	#[test]
+	fn stub_sizes() {
+		assert_eq!(decruncher(0x0810).len(), DECRUNCH_SIZE);
+		assert_eq!(mover(0x0801, 0x08a1, 0xf000, 1).len(), MOVER_SIZE);
+	}
+}
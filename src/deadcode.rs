@@ -0,0 +1,154 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::{ErrorMsg, rasm_error};
+
+pub type SymbolKey = (isize, String);
+
+pub struct Proc {
+	pub name: String,
+	pub start: usize,
+	pub size: usize,
+	pub references: HashSet<SymbolKey>,
+	pub live: bool,
+}
+
+#[derive(Default)]
+pub struct ProcTracker {
+	pub procs: Vec<Proc>,
+	pub labels: HashMap<SymbolKey, usize>,
+	pub roots: HashSet<SymbolKey>,
+
+	pub current: Option<usize>,
+	pub next_index: usize,
+	pub skipping: bool,
+	pub analyzed: bool,
+
+	pub code_seen: bool,
+	pub entry: Option<usize>,
+}
+
+impl ProcTracker {
+	pub fn reset(&mut self) {
+		self.current = None;
+		self.next_index = 0;
+		self.skipping = false;
+	}
+
+	pub fn collecting(&self) -> bool {
+		!self.analyzed
+	}
+
+	// Returns whether the procedure is kept
+	pub fn begin(&mut self, name: &str, pc: usize, line_num: usize) -> bool {
+		if self.current.is_some() {
+			rasm_error!(line_num, "{}", ".proc blocks cannot be nested");
+		}
+
+		let index = self.next_index;
+		self.next_index += 1;
+		self.current = Some(index);
+
+		if self.collecting() {
+			self.procs.push(Proc{name: name.into(), start: pc, size: 0, references: HashSet::new(), live: false});
+			true
+		} else {
+			self.skipping = !self.procs[index].live;
+			!self.skipping
+		}
+	}
+
+	pub fn end(&mut self, pc: usize, line_num: usize) {
+		match self.current.take() {
+			Some(index) => {
+				if self.collecting() {
+					let proc = &mut self.procs[index];
+					proc.size = pc - proc.start;
+				}
+
+				self.skipping = false;
+			},
+			None => {
+				rasm_error!(line_num, "{}", ".endproc without matching .proc");
+			},
+		}
+	}
+
+	pub fn define_label(&mut self, key: SymbolKey) {
+		if let (true, Some(index)) = (self.collecting(), self.current) {
+			self.labels.insert(key, index);
+		}
+	}
+
+	// The procedure holding the first byte of the program is where execution starts
+	pub fn code_emitted(&mut self) {
+		if self.collecting() && !self.code_seen {
+			self.code_seen = true;
+			self.entry = self.current;
+		}
+	}
+
+	pub fn reference(&mut self, key: SymbolKey) {
+		if !self.collecting() {
+			return;
+		}
+
+		match self.current {
+			Some(index) => self.procs[index].references.insert(key),
+			None => self.roots.insert(key),
+		};
+	}
+
+	// Marks every procedure reachable from the entry points, returns whether any are dead
+	pub fn analyze(&mut self, exports: &[String], entry: Option<&str>) -> bool {
+		let mut work = self.roots.iter().cloned().collect::<Vec<SymbolKey>>();
+		work.extend(exports.iter().map(|e| (-1, e.clone())));
+		work.extend(entry.map(symbol_key));
+		if let Some(index) = self.entry {
+			work.extend(self.labels.iter().filter(|(_, i)| **i == index).map(|(key, _)| key.clone()));
+		}
+
+		while let Some(key) = work.pop() {
+			if let Some(&index) = self.labels.get(&key) {
+				if !self.procs[index].live {
+					self.procs[index].live = true;
+					work.extend(self.procs[index].references.iter().cloned());
+				}
+			}
+		}
+
+		self.analyzed = true;
+		self.procs.iter().any(|p| !p.live)
+	}
+
+	pub fn is_dead(&self, key: &SymbolKey) -> bool {
+		self.analyzed && self.labels.get(key).is_some_and(|index| !self.procs[*index].live)
+	}
+
+	pub fn dead_labels(&self) -> Vec<SymbolKey> {
+		self.labels.iter().filter(|(_, index)| !self.procs[**index].live).map(|(key, _)| key.clone()).collect()
+	}
+
+	pub fn report(&self) -> String {
+		let dead = self.procs.iter().filter(|p| !p.live).collect::<Vec<&Proc>>();
+		if dead.is_empty() {
+			return String::new();
+		}
+
+		let mut report = String::new();
+		let total = dead.iter().map(|p| p.size).sum::<usize>();
+		writeln!(report, "Removed {} bytes in {} unreferenced procedures:", total, dead.len()).unwrap();
+		for proc in dead {
+			writeln!(report, "  {} ({} bytes)", proc.name, proc.size).unwrap();
+		}
+
+		report
+	}
+}
+
+fn symbol_key(name: &str) -> SymbolKey {
+	match name.strip_prefix("block").and_then(|n| n.split_once('.')) {
+		Some((block, name)) if block.parse::<isize>().is_ok() => (block.parse().unwrap(), name.into()),
+		_ => (-1, name.into()),
+	}
+}
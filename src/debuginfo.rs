@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+use crate::AssemblyState;
+use crate::symbols::{Symbol, SymbolKind};
+use crate::vice::BreakKind;
+
+pub struct LineRange {
+	pub line_num: usize,
+	pub start: u16,
+	pub end: u16,
+	pub segment: Option<usize>,
+	pub columns: (usize, usize),
+}
+
+pub fn source_columns(source: &str) -> (usize, usize) {
+	let code = source.find(';').map_or(source, |idx| &source[..idx]).trim_end();
+	let indent = code.len() - code.trim_start().len();
+	(indent + 1, code.len() + 1)
+}
+
+fn escape_xml(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn segment_name(assembly_state: &AssemblyState, segment: Option<usize>) -> String {
+	match (segment, assembly_state.memory_config.as_ref()) {
+		(Some(index), Some(config)) => config.segments[index].name.clone(),
+		_ => String::from("Default"),
+	}
+}
+
+pub fn write_c64debugger(source: &str, assembly_state: &AssemblyState, symbols: &[Symbol]) -> String {
+	let mut info = String::new();
+	writeln!(info, "<C64debugger version=\"1.0\">").unwrap();
+	writeln!(info, "\t<Sources values=\"INDEX,FILE\">").unwrap();
+	writeln!(info, "\t\t0,{}", escape_xml(source)).unwrap();
+	writeln!(info, "\t</Sources>").unwrap();
+
+	let mut segments = assembly_state.line_ranges.iter().map(|r| r.segment).collect::<Vec<Option<usize>>>();
+	segments.sort();
+	segments.dedup();
+
+	for segment in segments {
+		let name = escape_xml(&segment_name(assembly_state, segment));
+		writeln!(info, "\t<Segment name=\"{}\" dest=\"\" values=\"START,END,FILE_IDX,LINE1,COL1,LINE2,COL2\">", name).unwrap();
+		writeln!(info, "\t\t<Block name=\"{}\">", name).unwrap();
+		for range in assembly_state.line_ranges.iter().filter(|r| r.segment == segment) {
+			writeln!(info, "\t\t\t${:04x},${:04x},0,{},{},{},{}",
+				range.start, range.end, range.line_num, range.columns.0, range.line_num, range.columns.1).unwrap();
+		}
+
+		writeln!(info, "\t\t</Block>").unwrap();
+		writeln!(info, "\t</Segment>").unwrap();
+	}
+
+	writeln!(info, "\t<Labels values=\"SEGMENT,ADDRESS,NAME,FILE_IDX,LINE1,COL1,LINE2,COL2\">").unwrap();
+	for symbol in symbols.iter().filter(|s| s.kind == SymbolKind::Label) {
+		if let Some(line_num) = symbol.line_num {
+			let segment = assembly_state.label_lines.get(&(symbol.block, symbol.name.clone())).and_then(|l| l.1);
+			writeln!(info, "\t\t{},${:04x},{},0,{},1,{},{}", escape_xml(&segment_name(assembly_state, segment)),
+				symbol.value, escape_xml(&crate::vice::vice_label_name(symbol)), line_num, line_num, symbol.name.len() + 2).unwrap();
+		}
+	}
+
+	writeln!(info, "\t</Labels>").unwrap();
+
+	writeln!(info, "\t<Breakpoints values=\"SEGMENT,ADDRESS,ARGUMENT\">").unwrap();
+	for point in assembly_state.breakpoints.iter().filter(|p| p.kind == BreakKind::Break) {
+		writeln!(info, "\t\t{},${:04x},", escape_xml(&segment_name(assembly_state, point.segment)), point.addr).unwrap();
+	}
+
+	writeln!(info, "\t</Breakpoints>").unwrap();
+
+	writeln!(info, "\t<Watchpoints values=\"SEGMENT,ADDRESS1,ADDRESS2,ARGUMENT\">").unwrap();
+	for point in assembly_state.breakpoints.iter().filter(|p| p.kind == BreakKind::Watch) {
+		let mode = point.mode.as_deref().unwrap_or("");
+		writeln!(info, "\t\t{},${:04x},,{}", escape_xml(&segment_name(assembly_state, point.segment)), point.addr, mode).unwrap();
+	}
+
+	writeln!(info, "\t</Watchpoints>").unwrap();
+	writeln!(info, "</C64debugger>").unwrap();
+	info
+}
@@ -0,0 +1,329 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{ErrorMsg, rasm_error, rasm_warning};
+
+const SECTOR_SIZE: usize = 256;
+const SECTOR_DATA: usize = SECTOR_SIZE - 2;
+const ENTRY_SIZE: usize = 32;
+const NAME_LENGTH: usize = 16;
+const PADDING: u8 = 0xa0;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DiskFormat {
+	D64,
+	D71,
+	D81,
+}
+
+impl DiskFormat {
+	fn tracks(&self) -> usize {
+		match self {
+			Self::D64 => 35,
+			Self::D71 => 70,
+			Self::D81 => 80,
+		}
+	}
+
+	fn sectors(&self, track: usize) -> usize {
+		match self {
+			Self::D81 => 40,
+			_ => match (track - 1) % 35 + 1 {
+				1..=17 => 21,
+				18..=24 => 19,
+				25..=30 => 18,
+				_ => 17,
+			},
+		}
+	}
+
+	fn dir_track(&self) -> usize {
+		match self {
+			Self::D64 | Self::D71 => 18,
+			Self::D81 => 40,
+		}
+	}
+
+	fn first_dir_sector(&self) -> usize {
+		match self {
+			Self::D64 | Self::D71 => 1,
+			Self::D81 => 3,
+		}
+	}
+
+	fn interleave(&self) -> usize {
+		match self {
+			Self::D64 => 10,
+			Self::D71 => 6,
+			Self::D81 => 1,
+		}
+	}
+
+	fn dir_interleave(&self) -> usize {
+		match self {
+			Self::D64 | Self::D71 => 3,
+			Self::D81 => 1,
+		}
+	}
+
+	fn is_reserved(&self, track: usize) -> bool {
+		track == self.dir_track() || (*self == Self::D71 && track == 53)
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FileKind {
+	Seq,
+	Prg,
+}
+
+impl FileKind {
+	fn type_byte(&self) -> u8 {
+		match self {
+			Self::Seq => 0x81,
+			Self::Prg => 0x82,
+		}
+	}
+}
+
+pub struct DiskFile {
+	pub name: String,
+	pub kind: FileKind,
+	pub data: Vec<u8>,
+}
+
+impl DiskFile {
+	pub fn from_arg(arg: &str) -> Self {
+		let args = arg.split(',').map(|a| a.trim()).collect::<Vec<&str>>();
+		let path = args[0];
+		let data = fs::read(path).unwrap_or_else(|_| rasm_error!(0, "Failed to read disk file {}", path));
+
+		let name = match args.get(1) {
+			Some(name) => name.to_string(),
+			None => Path::new(path).file_stem().map_or(String::from(path), |s| s.to_string_lossy().to_string()),
+		};
+
+		let kind = match args.get(2).map(|k| k.to_lowercase()) {
+			Some(k) if k == "prg" => FileKind::Prg,
+			Some(k) if k == "seq" => FileKind::Seq,
+			Some(k) => rasm_error!(0, "Invalid disk file type \"{}\"", k),
+			None if path.to_lowercase().ends_with(".seq") => FileKind::Seq,
+			None => FileKind::Prg,
+		};
+
+		Self{name, kind, data}
+	}
+}
+
+fn petscii_name(name: &str) -> [u8; NAME_LENGTH] {
+	if name.len() > NAME_LENGTH {
+		rasm_warning!(0, "Name \"{}\" is longer than {} characters and will be truncated", name, NAME_LENGTH);
+	}
+
+	let mut bytes = [PADDING; NAME_LENGTH];
+	for (byte, c) in bytes.iter_mut().zip(name.to_uppercase().bytes()) {
+		*byte = c;
+	}
+
+	bytes
+}
+
+struct DiskImage {
+	format: DiskFormat,
+	data: Vec<u8>,
+	used: Vec<Vec<bool>>,
+}
+
+impl DiskImage {
+	fn new(format: DiskFormat) -> Self {
+		let used = (1..=format.tracks()).map(|t| vec![false; format.sectors(t)]).collect::<Vec<Vec<bool>>>();
+		let size = used.iter().map(|t| t.len()).sum::<usize>() * SECTOR_SIZE;
+		Self{format, data: vec![0; size], used}
+	}
+
+	fn sector(&mut self, track: usize, sector: usize) -> &mut [u8] {
+		let offset = (1..track).map(|t| self.format.sectors(t)).sum::<usize>() * SECTOR_SIZE + sector * SECTOR_SIZE;
+		&mut self.data[offset..offset + SECTOR_SIZE]
+	}
+
+	fn allocate_on(&mut self, track: usize, start: usize) -> Option<usize> {
+		let count = self.used[track - 1].len();
+		let sector = (0..count).map(|i| (start + i) % count).find(|s| !self.used[track - 1][*s])?;
+		self.used[track - 1][sector] = true;
+		Some(sector)
+	}
+
+	fn allocate_file(&mut self, count: usize) -> Vec<(usize, usize)> {
+		let dir_track = self.format.dir_track();
+		let order = (1..dir_track).rev().chain(dir_track + 1..=self.format.tracks())
+			.filter(|t| !self.format.is_reserved(*t)).collect::<Vec<usize>>();
+
+		let mut sectors = vec![];
+		let mut next = 0;
+		for track in order {
+			while sectors.len() < count {
+				match self.allocate_on(track, next) {
+					Some(sector) => {
+						sectors.push((track, sector));
+						next = (sector + self.format.interleave()) % self.format.sectors(track);
+					},
+					None => break,
+				}
+			}
+
+			if sectors.len() == count {
+				return sectors;
+			}
+
+			next = 0;
+		}
+
+		rasm_error!(0, "{}", "Disk image is full");
+	}
+
+	fn write_file(&mut self, file: &DiskFile) -> (usize, usize, usize) {
+		let count = file.data.len().div_ceil(SECTOR_DATA).max(1);
+		let sectors = self.allocate_file(count);
+		for (index, (track, sector)) in sectors.iter().enumerate() {
+			let start = index * SECTOR_DATA;
+			let data = &file.data[start..(start + SECTOR_DATA).min(file.data.len())];
+			let link = match sectors.get(index + 1) {
+				Some(next) => [next.0 as u8, next.1 as u8],
+				None => [0, (data.len() + 1) as u8],
+			};
+
+			let block = self.sector(*track, *sector);
+			block[0..2].copy_from_slice(&link);
+			block[2..2 + data.len()].copy_from_slice(data);
+		}
+
+		(sectors[0].0, sectors[0].1, count)
+	}
+
+	fn write_directory(&mut self, entries: &[[u8; ENTRY_SIZE]]) {
+		let track = self.format.dir_track();
+		let mut dir_sectors = vec![self.format.first_dir_sector()];
+		self.used[track - 1][dir_sectors[0]] = true;
+		while dir_sectors.len() * 8 < entries.len() {
+			let next = (dir_sectors.last().unwrap() + self.format.dir_interleave()) % self.format.sectors(track);
+			let sector = self.allocate_on(track, next).unwrap_or_else(
+				|| rasm_error!(0, "Too many files for the disk directory ({} entries)", entries.len())
+			);
+			dir_sectors.push(sector);
+		}
+
+		for (index, sector) in dir_sectors.iter().enumerate() {
+			let link = match dir_sectors.get(index + 1) {
+				Some(next) => [track as u8, *next as u8],
+				None => [0, 0xff],
+			};
+
+			let block = self.sector(track, *sector);
+			for (slot, entry) in entries.iter().skip(index * 8).take(8).enumerate() {
+				block[slot * ENTRY_SIZE..(slot + 1) * ENTRY_SIZE].copy_from_slice(entry);
+			}
+
+			block[0..2].copy_from_slice(&link);
+		}
+	}
+
+	fn bam_entry(&self, track: usize) -> Vec<u8> {
+		let used = &self.used[track - 1];
+		let mut entry = vec![used.iter().filter(|u| !**u).count() as u8];
+		entry.extend((0..used.len().div_ceil(8)).map(|byte| {
+			(0..8).filter(|bit| used.get(byte * 8 + bit) == Some(&false)).fold(0u8, |b, bit| b | (1 << bit))
+		}));
+
+		entry
+	}
+
+	fn write_bam(&mut self, name: &[u8; NAME_LENGTH], id: &[u8; 2]) {
+		match self.format {
+			DiskFormat::D64 | DiskFormat::D71 => {
+				let entries = (1..=35).map(|t| self.bam_entry(t)).collect::<Vec<Vec<u8>>>();
+				let double_sided = self.format == DiskFormat::D71;
+				let side_two = if double_sided { (36..=70).map(|t| self.bam_entry(t)).collect::<Vec<Vec<u8>>>() } else { vec![] };
+
+				let block = self.sector(18, 0);
+				block[0..4].copy_from_slice(&[18, 1, b'A', if double_sided { 0x80 } else { 0 }]);
+				for (index, entry) in entries.iter().enumerate() {
+					block[4 + index * 4..8 + index * 4].copy_from_slice(entry);
+				}
+
+				block[0x90..0xa0].copy_from_slice(name);
+				block[0xa0..0xab].copy_from_slice(&[PADDING, PADDING, id[0], id[1], PADDING, b'2', b'A', PADDING, PADDING, PADDING, PADDING]);
+				for (index, entry) in side_two.iter().enumerate() {
+					block[0xdd + index] = entry[0];
+				}
+
+				if double_sided {
+					let block = self.sector(53, 0);
+					for (index, entry) in side_two.iter().enumerate() {
+						block[index * 3..index * 3 + 3].copy_from_slice(&entry[1..]);
+					}
+				}
+			},
+			DiskFormat::D81 => {
+				let header = self.sector(40, 0);
+				header[0..4].copy_from_slice(&[40, 3, b'D', 0]);
+				header[0x04..0x14].copy_from_slice(name);
+				header[0x14..0x1d].copy_from_slice(&[PADDING, PADDING, id[0], id[1], PADDING, b'3', b'D', PADDING, PADDING]);
+
+				for side in 0..2 {
+					let entries = (side * 40 + 1..=side * 40 + 40).map(|t| self.bam_entry(t)).collect::<Vec<Vec<u8>>>();
+					let block = self.sector(40, side + 1);
+					let link = if side == 0 { [40, 2] } else { [0, 0xff] };
+					block[0..8].copy_from_slice(&[link[0], link[1], b'D', 0xbb, id[0], id[1], 0xc0, 0]);
+					for (index, entry) in entries.iter().enumerate() {
+						block[0x10 + index * 6..0x16 + index * 6].copy_from_slice(entry);
+					}
+				}
+			},
+		}
+	}
+}
+
+pub fn write_disk(format: DiskFormat, disk_name: &str, files: &[DiskFile]) -> Vec<u8> {
+	let mut image = DiskImage::new(format);
+
+	let system_sectors = match format {
+		DiskFormat::D64 | DiskFormat::D71 => vec![(18, 0)],
+		DiskFormat::D81 => vec![(40, 0), (40, 1), (40, 2)],
+	};
+
+	for (track, sector) in system_sectors {
+		image.used[track - 1][sector] = true;
+	}
+
+	if format == DiskFormat::D71 {
+		image.used[52].iter_mut().for_each(|u| *u = true);
+	}
+
+	let mut entries = vec![];
+	for file in files.iter() {
+		let (track, sector, size) = image.write_file(file);
+		let mut entry = [0u8; ENTRY_SIZE];
+		entry[2] = file.kind.type_byte();
+		entry[3] = track as u8;
+		entry[4] = sector as u8;
+		entry[5..0x15].copy_from_slice(&petscii_name(&file.name));
+		entry[0x1e] = (size & 0xff) as u8;
+		entry[0x1f] = (size >> 8) as u8;
+		entries.push(entry);
+	}
+
+	image.write_directory(&entries);
+
+	let (name, id) = match disk_name.split_once(',') {
+		Some((name, id)) => (name, id),
+		None => (disk_name, "01"),
+	};
+
+	let mut id_bytes = [PADDING; 2];
+	for (byte, c) in id_bytes.iter_mut().zip(id.to_uppercase().bytes()) {
+		*byte = c;
+	}
+
+	image.write_bam(&petscii_name(name), &id_bytes);
+	image.data
+}
@@ -0,0 +1,67 @@
+use std::fmt::Write;
+
+use crate::output::Chunk;
+use crate::utility::*;
+
+const RECORD_SIZE: usize = 16;
+
+fn sorted_chunks(chunks: &[Chunk]) -> Vec<&Chunk> {
+	let mut sorted = chunks.iter().filter(|c| !c.bytes.is_empty()).collect::<Vec<&Chunk>>();
+	sorted.sort_by_key(|c| c.start);
+	sorted
+}
+
+fn hex_record(text: &mut String, start: &str, fields: &[u8], checksum: u8) {
+	text.push_str(start);
+	for byte in fields.iter() {
+		write!(text, "{:02X}", byte).unwrap();
+	}
+
+	writeln!(text, "{:02X}", checksum).unwrap();
+}
+
+pub fn intel_hex(chunks: &[Chunk]) -> String {
+	let mut text = String::new();
+	for chunk in sorted_chunks(chunks) {
+		for (index, data) in chunk.bytes.chunks(RECORD_SIZE).enumerate() {
+			let addr = (chunk.start as usize + index * RECORD_SIZE) as u16;
+			let mut fields = vec![data.len() as u8, hi8(addr), lo8(addr), 0x00];
+			fields.extend_from_slice(data);
+
+			let sum = fields.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+			hex_record(&mut text, ":", &fields, sum.wrapping_neg());
+		}
+	}
+
+	text.push_str(":00000001FF\n");
+	text
+}
+
+fn srec(text: &mut String, kind: char, addr: u16, data: &[u8]) {
+	let mut fields = vec![data.len() as u8 + 3, hi8(addr), lo8(addr)];
+	fields.extend_from_slice(data);
+
+	let sum = fields.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+	hex_record(text, &format!("S{}", kind), &fields, !sum);
+}
+
+pub fn s_record(chunks: &[Chunk], name: &str) -> String {
+	let mut text = String::new();
+	srec(&mut text, '0', 0, name.as_bytes());
+
+	let sorted = sorted_chunks(chunks);
+	let mut count = 0;
+	for chunk in sorted.iter() {
+		for (index, data) in chunk.bytes.chunks(RECORD_SIZE).enumerate() {
+			srec(&mut text, '1', (chunk.start as usize + index * RECORD_SIZE) as u16, data);
+			count += 1;
+		}
+	}
+
+	if count <= 0xffff {
+		srec(&mut text, '5', count as u16, &[]);
+	}
+
+	srec(&mut text, '9', sorted.first().map_or(0, |c| c.start), &[]);
+	text
+}
@@ -3,6 +3,7 @@ use maplit::hashmap;
 use std::collections::HashMap;
 
 use crate::regexes;
+use crate::object::RelocTarget;
 use crate::utility::*;
 use crate::{Pass, AssemblyState, ErrorMsg, rasm_error};
 
@@ -38,11 +39,18 @@ fn addr_default(op_str: &str, mnemonic_map: &HashMap<AddressMode, u8>, assembly_
 		},
 	};
 
+	let wide = assembly_state.fixup_is_wide();
 	if mnemonic_map.contains_key(&AddressMode::Relative) {
+		if let Some(fixup) = assembly_state.last_fixup.take() {
+			if fixup.target != RelocTarget::Segment(assembly_state.current_segment.unwrap()) {
+				rasm_error!(assembly_state.line_num, "Branch target \"{}\" must be in the same segment", op_str);
+			}
+		}
+
 		let diff = !(assembly_state.program_counter as isize - op as isize) - 1;
 		(AddressMode::Relative, vec![diff as u8])
 	} else {
-		if op <= u8::MAX as u16 {
+		if op <= u8::MAX as u16 && !wide {
 			(AddressMode::Zeropage, vec![op as u8])
 		} else {
 			(AddressMode::Absolute, vec![lo8(op), hi8(op)])
@@ -68,7 +76,7 @@ pub fn get_instruction_bytes(mnemonic: &str, operand: &str, assembly_state: &mut
 				},
 			};
 
-			let result = (regex.1)(op);
+			let result = (regex.1)(op, assembly_state.fixup_is_wide());
 
 			addr_mode = result.0;
 			operand_vec = result.1;
@@ -80,18 +88,42 @@ pub fn get_instruction_bytes(mnemonic: &str, operand: &str, assembly_state: &mut
 	let mnemonic_map = OPCODES.get(mnemonic).unwrap();
 
 	if !matched {
-		let result = addr_default(operand, &mnemonic_map, assembly_state);
+		let result = addr_default(operand, mnemonic_map, assembly_state);
 		addr_mode = result.0;
 		operand_vec = result.1;
 	}
 
 	let opcode = *mnemonic_map.get(&addr_mode).unwrap();
 
+	if !operand_vec.is_empty() {
+		assembly_state.relocate(1, operand_vec.len());
+	}
+
 	let mut ret = vec![opcode];
 	ret.extend(operand_vec);
 	ret
 }
 
+// Base cycle counts indexed by opcode, without page crossing or branch penalties
+pub const CYCLES: [u8; 256] = [
+	7, 6, 0, 0, 0, 3, 5, 0, 3, 2, 2, 0, 0, 4, 6, 0,
+	2, 5, 0, 0, 0, 4, 6, 0, 2, 4, 0, 0, 0, 4, 7, 0,
+	6, 6, 0, 0, 3, 3, 5, 0, 4, 2, 2, 0, 4, 4, 6, 0,
+	2, 5, 0, 0, 0, 4, 6, 0, 2, 4, 0, 0, 0, 4, 7, 0,
+	6, 6, 0, 0, 0, 3, 5, 0, 3, 2, 2, 0, 3, 4, 6, 0,
+	2, 5, 0, 0, 0, 4, 6, 0, 2, 4, 0, 0, 0, 4, 7, 0,
+	6, 6, 0, 0, 0, 3, 5, 0, 4, 2, 2, 0, 5, 4, 6, 0,
+	2, 5, 0, 0, 0, 4, 6, 0, 2, 4, 0, 0, 0, 4, 7, 0,
+	0, 6, 0, 0, 3, 3, 3, 0, 2, 0, 2, 0, 4, 4, 4, 0,
+	2, 6, 0, 0, 4, 4, 4, 0, 2, 5, 2, 0, 0, 5, 0, 0,
+	2, 6, 2, 0, 3, 3, 3, 0, 2, 2, 2, 0, 4, 4, 4, 0,
+	2, 5, 0, 0, 4, 4, 4, 0, 2, 4, 2, 0, 4, 4, 4, 0,
+	2, 6, 0, 0, 3, 3, 5, 0, 2, 2, 2, 0, 4, 4, 6, 0,
+	2, 5, 0, 0, 0, 4, 6, 0, 2, 4, 0, 0, 0, 4, 7, 0,
+	2, 6, 0, 0, 3, 3, 5, 0, 2, 2, 2, 0, 4, 4, 6, 0,
+	2, 5, 0, 0, 0, 4, 6, 0, 2, 4, 0, 0, 0, 4, 7, 0,
+];
+
 lazy_static! {
 	pub static ref OPCODES: HashMap<&'static str, HashMap<AddressMode, u8>> = {
 		type A = AddressMode;
@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::object::{ObjectFile, Reader, push_string, push_u16, push_u32};
+use crate::{ErrorMsg, rasm_error};
+
+const LIBRARY_MAGIC: &[u8] = b"RASMLIB";
+const LIBRARY_VERSION: u8 = 1;
+
+pub struct Library {
+	pub members: Vec<ObjectFile>,
+}
+
+impl Library {
+	pub fn is_library(bytes: &[u8]) -> bool {
+		bytes.starts_with(LIBRARY_MAGIC)
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = LIBRARY_MAGIC.to_vec();
+		bytes.push(LIBRARY_VERSION);
+
+		push_u16(&mut bytes, self.members.len() as u16);
+		for member in self.members.iter() {
+			let data = member.to_bytes();
+			push_string(&mut bytes, &member.name);
+			push_u32(&mut bytes, data.len() as u32);
+			bytes.extend(data);
+		}
+
+		bytes
+	}
+
+	pub fn from_bytes(bytes: &[u8], name: &str) -> Self {
+		let mut reader = Reader::new(bytes, name);
+		if reader.take(LIBRARY_MAGIC.len()) != LIBRARY_MAGIC {
+			rasm_error!(0, "{} is not a rasm library", name);
+		}
+
+		if reader.u8() != LIBRARY_VERSION {
+			rasm_error!(0, "Unsupported library version in {}", name);
+		}
+
+		let members = (0..reader.u16()).map(|_| {
+			let member_name = reader.string();
+			let len = reader.u32() as usize;
+			ObjectFile::from_bytes(reader.take(len), &format!("{}({})", name, member_name))
+		}).collect();
+
+		Self{members}
+	}
+
+	pub fn listing(&self) -> String {
+		let mut listing = String::new();
+		for member in self.members.iter() {
+			let size = member.segments.iter().map(|s| s.size as usize).sum::<usize>();
+			writeln!(listing, "{} ({} bytes)", member_name(&member.name), size).unwrap();
+			for export in member.exports.iter() {
+				writeln!(listing, "  {}", export.name).unwrap();
+			}
+		}
+
+		listing
+	}
+}
+
+fn member_name(name: &str) -> &str {
+	match (name.find('('), name.ends_with(')')) {
+		(Some(pos), true) => &name[pos + 1..name.len() - 1],
+		_ => name,
+	}
+}
+
+pub fn select_members(mut objects: Vec<ObjectFile>, libraries: Vec<Library>) -> Vec<ObjectFile> {
+	let mut defined = objects.iter().flat_map(|o| o.exports.iter().map(|e| e.name.clone())).collect::<HashSet<String>>();
+	let mut needed = objects.iter().flat_map(|o| o.imports.iter().map(|i| i.name.clone()))
+		.filter(|n| !defined.contains(n))
+		.collect::<HashSet<String>>();
+
+	let mut available = libraries.into_iter().flat_map(|l| l.members).map(Some).collect::<Vec<Option<ObjectFile>>>();
+	loop {
+		let pulled = available.iter_mut().find(|m| {
+			m.as_ref().is_some_and(|m| m.exports.iter().any(|e| needed.contains(&e.name)))
+		}).and_then(|m| m.take());
+
+		let member = match pulled {
+			Some(member) => member,
+			None => break,
+		};
+
+		for export in member.exports.iter() {
+			needed.remove(&export.name);
+			defined.insert(export.name.clone());
+		}
+
+		for import in member.imports.iter() {
+			if !defined.contains(&import.name) {
+				needed.insert(import.name.clone());
+			}
+		}
+
+		objects.push(member);
+	}
+
+	objects
+}
+
+pub fn library_command(args: Vec<String>) {
+	match args.first().map(|a| a.as_str()) {
+		Some("-t") => {
+			let path = args.get(1).unwrap_or_else(|| rasm_error!(0, "{}", "No library file specified"));
+			let bytes = fs::read(path).unwrap_or_else(|_| rasm_error!(0, "Failed to open library file {}", path));
+			print!("{}", Library::from_bytes(&bytes, path).listing());
+		},
+		Some(path) => {
+			if args.len() < 2 {
+				rasm_error!(0, "{}", "No object files specified for library");
+			}
+
+			let members = args[1..].iter().map(|object_path| {
+				let bytes = fs::read(object_path).unwrap_or_else(
+					|_| rasm_error!(0, "Failed to open object file {}", object_path)
+				);
+
+				let name = Path::new(object_path).file_name().map_or(object_path.clone(), |n| n.to_string_lossy().into_owned());
+				ObjectFile::from_bytes(&bytes, &name)
+			}).collect();
+
+			let library = Library{members};
+			fs::write(path, library.to_bytes()).unwrap_or_else(
+				|_| rasm_error!(0, "Failed to write to library file {}", path)
+			);
+		},
+		None => {
+			rasm_error!(0, "{}", "No library file specified");
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::object::{Export, Import, ObjectSegment};
+	use crate::segments::SegmentKind;
+
+	fn object(name: &str, exports: &[&str], imports: &[&str]) -> ObjectFile {
+		ObjectFile{
+			name: name.into(),
+			segments: vec![ObjectSegment{name: "CODE".into(), kind: SegmentKind::ReadOnly, size: 1, data: vec![0x60]}],
+			relocations: vec![],
+			imports: imports.iter().map(|i| Import{name: i.to_string(), zeropage: false}).collect(),
+			exports: exports.iter().map(|e| Export{name: e.to_string(), segment: Some(0), value: 0}).collect(),
+		}
+	}
+
+	#[test]
+	fn link_only_referenced_members() {
+		let library = Library{members: vec![
+			object("unused.o", &["unused"], &[]),
+			object("print.o", &["print"], &["putc"]),
+			object("putc.o", &["putc"], &[]),
+		]};
+
+		let library = Library::from_bytes(&library.to_bytes(), "test.lib");
+		assert_eq!(library.listing(), "unused.o (1 bytes)\n  unused\nprint.o (1 bytes)\n  print\nputc.o (1 bytes)\n  putc\n");
+
+		let objects = select_members(vec![object("main.o", &["start"], &["print"])], vec![library]);
+		let names = objects.iter().map(|o| o.name.as_str()).collect::<Vec<&str>>();
+		assert_eq!(names, vec!["main.o", "test.lib(print.o)", "test.lib(putc.o)"]);
+	}
+
+	#[test]
+	fn objects_take_precedence_over_members() {
+		let library = Library{members: vec![object("putc.o", &["putc"], &[])]};
+		let objects = select_members(vec![object("main.o", &[], &["putc"]), object("putc.o", &["putc"], &[])], vec![library]);
+		assert_eq!(objects.len(), 2);
+	}
+}
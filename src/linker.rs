@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use crate::object::{ObjectFile, RelocKind, RelocTarget};
+use crate::output::Chunk;
+use crate::segments::MemoryConfig;
+use crate::symbols::{Symbol, SymbolKind};
+use crate::utility::*;
+use crate::{ErrorMsg, rasm_error};
+
+pub fn link(objects: &[ObjectFile], config: &mut MemoryConfig) -> (Vec<Chunk>, Vec<Symbol>) {
+	let mut offsets = vec![Vec::<usize>::new(); objects.len()];
+	let mut sizes = vec![0usize; config.segments.len()];
+	let mut placement = vec![Vec::<usize>::new(); objects.len()];
+
+	for (index, object) in objects.iter().enumerate() {
+		for segment in object.segments.iter() {
+			let config_index = config.segments.iter().position(|s| s.name == segment.name).unwrap_or_else(
+				|| rasm_error!(0, "Segment \"{}\" in {} is not defined in the memory configuration", segment.name, object.name)
+			);
+
+			if config.segments[config_index].kind != segment.kind {
+				rasm_error!(0, "Segment \"{}\" in {} does not match the type in the memory configuration", segment.name, object.name);
+			}
+
+			placement[index].push(config_index);
+			offsets[index].push(sizes[config_index]);
+			sizes[config_index] += segment.size as usize;
+		}
+	}
+
+	for (segment, size) in config.segments.iter_mut().zip(sizes) {
+		segment.size = size;
+	}
+
+	config.layout();
+
+	let bases = objects.iter().enumerate().map(|(index, object)| {
+		(0..object.segments.len()).map(|s| {
+			config.segments[placement[index][s]].base as usize + offsets[index][s]
+		}).collect::<Vec<usize>>()
+	}).collect::<Vec<Vec<usize>>>();
+
+	let mut symbols = HashMap::<String, (u16, usize)>::new();
+	let mut duplicates = vec![];
+	for (index, object) in objects.iter().enumerate() {
+		for export in object.exports.iter() {
+			let value = match export.segment {
+				Some(segment) => (bases[index][segment] + export.value as usize) as u16,
+				None => export.value,
+			};
+
+			if let Some((_, other)) = symbols.insert(export.name.clone(), (value, index)) {
+				duplicates.push(format!("\"{}\" ({} and {})", export.name, objects[other].name, object.name));
+			}
+		}
+	}
+
+	if !duplicates.is_empty() {
+		rasm_error!(0, "Duplicate symbols: {}", duplicates.join(", "));
+	}
+
+	let mut undefined = vec![];
+	let mut chunks = vec![];
+	for (index, object) in objects.iter().enumerate() {
+		let mut data = object.segments.iter().map(|s| s.data.clone()).collect::<Vec<Vec<u8>>>();
+
+		for reloc in object.relocations.iter() {
+			let base = match &reloc.target {
+				RelocTarget::Segment(segment) => bases[index][*segment] as u16,
+				RelocTarget::Import(name) => match symbols.get(name) {
+					Some((value, _)) => *value,
+					None => {
+						let entry = format!("\"{}\" (referenced in {})", name, object.name);
+						if !undefined.contains(&entry) {
+							undefined.push(entry);
+						}
+
+						continue;
+					},
+				},
+			};
+
+			let value = reloc.value.wrapping_add(base);
+			let bytes = &mut data[reloc.segment];
+			let offset = reloc.offset as usize;
+			match reloc.kind {
+				RelocKind::Word => {
+					bytes[offset] = lo8(value);
+					bytes[offset + 1] = hi8(value);
+				},
+				RelocKind::Lo => bytes[offset] = lo8(value),
+				RelocKind::Hi => bytes[offset] = hi8(value),
+				RelocKind::Zeropage => {
+					if value > u8::MAX as u16 {
+						rasm_error!(0, "Zero page relocation in {} resolves to ${:04x}", object.name, value);
+					}
+
+					bytes[offset] = value as u8;
+				},
+			}
+		}
+
+		for (segment, bytes) in data.into_iter().enumerate() {
+			if !bytes.is_empty() {
+				let mut chunk = Chunk::new(bases[index][segment] as u16, 0);
+				chunk.bytes = bytes;
+				chunk.segment = Some(placement[index][segment]);
+				chunks.push(chunk);
+			}
+		}
+	}
+
+	if !undefined.is_empty() {
+		rasm_error!(0, "Undefined symbols: {}", undefined.join(", "));
+	}
+
+	let mut exported = symbols.into_iter().map(|(name, (value, _))| {
+		Symbol{name, block: -1, value, kind: SymbolKind::Label, line_num: None}
+	}).collect::<Vec<Symbol>>();
+	exported.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+
+	(chunks, exported)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::object::{Export, Import, ObjectSegment, Relocation};
+	use crate::segments::SegmentKind;
+
+	const CONFIG: &str = "MEMORY {\n\tMAIN: start = $1000, size = $0100;\n}\n\nSEGMENTS {\n\tCODE: load = MAIN, type = ro;\n\tDATA: load = MAIN, type = rw;\n}\n";
+
+	fn segment(name: &str, kind: SegmentKind, data: Vec<u8>) -> ObjectSegment {
+		ObjectSegment{name: name.into(), kind, size: data.len() as u16, data}
+	}
+
+	fn objects() -> Vec<ObjectFile> {
+		// jsr helper; lda table; rts / table: .word start
+		let main = ObjectFile{
+			name: "main.o".into(),
+			segments: vec![
+				segment("CODE", SegmentKind::ReadOnly, vec![0x20, 0, 0, 0xad, 0, 0, 0x60]),
+				segment("DATA", SegmentKind::ReadWrite, vec![0, 0]),
+			],
+			relocations: vec![
+				Relocation{segment: 0, offset: 1, kind: RelocKind::Word, target: RelocTarget::Import("helper".into()), value: 0},
+				Relocation{segment: 0, offset: 4, kind: RelocKind::Word, target: RelocTarget::Segment(1), value: 0},
+				Relocation{segment: 1, offset: 0, kind: RelocKind::Word, target: RelocTarget::Segment(0), value: 0},
+			],
+			imports: vec![Import{name: "helper".into(), zeropage: false}],
+			exports: vec![Export{name: "start".into(), segment: Some(0), value: 0}],
+		};
+
+		let helper = ObjectFile{
+			name: "helper.o".into(),
+			segments: vec![segment("CODE", SegmentKind::ReadOnly, vec![0xea, 0x60])],
+			relocations: vec![],
+			imports: vec![],
+			exports: vec![Export{name: "helper".into(), segment: Some(0), value: 1}],
+		};
+
+		vec![main, helper]
+	}
+
+	fn round_trip(object: &ObjectFile) -> ObjectFile {
+		ObjectFile::from_bytes(&object.to_bytes(), &object.name)
+	}
+
+	#[test]
+	fn link_round_tripped_objects() {
+		let objects = objects().iter().map(round_trip).collect::<Vec<ObjectFile>>();
+		let mut config = MemoryConfig::parse(CONFIG);
+		let (chunks, symbols) = link(&objects, &mut config);
+
+		let image = chunks.iter().map(|c| (c.start, c.bytes.clone())).collect::<Vec<(u16, Vec<u8>)>>();
+		assert_eq!(image, vec![
+			(0x1000, vec![0x20, 0x08, 0x10, 0xad, 0x09, 0x10, 0x60]),
+			(0x1009, vec![0x00, 0x10]),
+			(0x1007, vec![0xea, 0x60]),
+		]);
+
+		let values = symbols.iter().map(|s| (s.name.as_str(), s.value)).collect::<Vec<(&str, u16)>>();
+		assert_eq!(values, vec![("start", 0x1000), ("helper", 0x1008)]);
+	}
+
+	#[test]
+	#[should_panic]
+	fn reject_relocation_past_segment_end() {
+		let mut object = objects().remove(0);
+		object.relocations[0].offset = 6;
+		round_trip(&object);
+	}
+
+	#[test]
+	#[should_panic]
+	fn reject_invalid_export_segment() {
+		let mut object = objects().remove(0);
+		object.exports[0].segment = Some(2);
+		round_trip(&object);
+	}
+}
@@ -0,0 +1,45 @@
+use std::fmt::Write;
+
+use crate::symbols::Symbol;
+
+const BYTES_PER_ROW: usize = 4;
+
+pub struct ListingLine {
+	pub line_num: usize,
+	pub addr: Option<u16>,
+	pub bytes: Vec<u8>,
+	pub cycles: Option<u8>,
+	pub source: String,
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<String>>().join(" ")
+}
+
+pub fn write_listing(lines: &[ListingLine], symbols: &[Symbol]) -> String {
+	let mut listing = String::new();
+	writeln!(listing, "{:>6}  {:<4}  {:<11}  {:>3}  Source", "Line", "Addr", "Bytes", "Cyc").unwrap();
+
+	for line in lines.iter() {
+		let addr = line.addr.map_or(String::from("----"), |a| format!("{:04x}", a));
+		let cycles = line.cycles.map_or(String::new(), |c| c.to_string());
+		let mut rows = line.bytes.chunks(BYTES_PER_ROW);
+		let first = rows.next().map_or(String::new(), hex_bytes);
+
+		writeln!(listing, "{:>6}  {}  {:<11}  {:>3}  {}", line.line_num, addr, first, cycles, line.source).unwrap();
+		for (index, row) in rows.enumerate() {
+			let row_addr = line.addr.map_or(0, |a| a as usize + (index + 1) * BYTES_PER_ROW) as u16;
+			writeln!(listing, "{:>6}  {:04x}  {}", "", row_addr, hex_bytes(row)).unwrap();
+		}
+	}
+
+	let mut sorted = symbols.iter().collect::<Vec<&Symbol>>();
+	sorted.sort_by_key(|s| s.scoped_name());
+
+	writeln!(listing, "\nSymbols:").unwrap();
+	for symbol in sorted {
+		writeln!(listing, "  {:<32} ${:04x}  {}", symbol.scoped_name(), symbol.value, symbol.kind.name()).unwrap();
+	}
+
+	listing
+}
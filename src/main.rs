@@ -2,23 +2,59 @@ extern crate lazy_static;
 extern crate regex;
 extern crate maplit;
 
+mod allocator;
+mod basic;
+mod cart;
+mod crunch;
+mod deadcode;
+mod debuginfo;
+mod disk;
+mod hexfile;
 mod instructions;
+mod library;
+mod linker;
+mod listing;
+mod mapfile;
+mod o65;
+mod object;
+mod output;
 mod regexes;
+mod report;
+mod segments;
+mod sourcearray;
+mod symbols;
+mod tape;
 mod target;
 mod utility;
+mod vice;
 
 use std::env;
 use std::fs::{self, File};
 use std::io::{self, BufRead, SeekFrom};
 use std::collections::HashMap;
 use std::io::Seek;
+use std::path::Path;
 
 use crate::utility::*;
 use crate::target::*;
-use crate::instructions::get_instruction_bytes;
+use crate::instructions::{get_instruction_bytes, CYCLES};
+use crate::basic::BasicVersion;
+use crate::allocator::{Allocator, VarSpace};
+use crate::object::{Fixup, FixupKind, Import, ObjectFile, RelocKind, RelocTarget, Relocation};
+use crate::cart::CartType;
+use crate::deadcode::ProcTracker;
+use crate::disk::DiskFile;
+use crate::debuginfo::LineRange;
+use crate::library::Library;
+use crate::listing::ListingLine;
+use crate::output::{Chunk, OutputFormat, OutputOptions};
+use crate::segments::{MemoryConfig, SegmentKind};
+use crate::vice::{BreakKind, Breakpoint};
 
 use maplit::hashmap;
 
+pub const DEFAULT_ORIGIN: u16 = 0x0801;
+
 #[derive(PartialEq)]
 pub enum Pass {
 	Constant,
@@ -37,7 +73,305 @@ pub struct AssemblyState {
 	pub max_block: isize,
 
 	pub labels: HashMap<isize, HashMap<String, u16>>,
+	pub load_labels: HashMap<isize, HashMap<String, u16>>,
 	pub constants: HashMap<String, u16>,
+
+	pub chunks: Vec<Chunk>,
+	pub current_chunk: usize,
+
+	pub memory_config: Option<MemoryConfig>,
+	pub current_segment: Option<usize>,
+
+	pub allocator: Allocator,
+
+	pub pseudopc: Option<(u16, usize)>,
+	pub virtual_block: Option<(usize, Option<(u16, usize)>)>,
+
+	pub relocatable: bool,
+	pub label_segments: HashMap<(isize, String), (usize, bool)>,
+	pub imports: Vec<Import>,
+	pub exports: Vec<String>,
+	pub relocations: Vec<Relocation>,
+	pub last_fixup: Option<Fixup>,
+	pub value_target: Option<RelocTarget>,
+
+	pub procs: ProcTracker,
+
+	pub listing: Option<Vec<ListingLine>>,
+	pub line_bytes: Vec<u8>,
+	pub line_cycles: Option<u8>,
+	pub line_addr: Option<usize>,
+
+	pub breakpoints: Vec<Breakpoint>,
+	pub line_ranges: Vec<LineRange>,
+	pub label_lines: HashMap<(isize, String), (usize, Option<usize>)>,
+
+	pub current_bank: Option<usize>,
+	pub label_banks: HashMap<(isize, String), usize>,
+	pub trampolines: Vec<String>,
+}
+
+impl AssemblyState {
+	pub fn set_origin(&mut self, addr: u16) {
+		if self.pseudopc.is_some() {
+			rasm_error!(self.line_num, "{}", "Origin cannot be set inside a .pseudopc block");
+		}
+
+		if self.virtual_block.is_some() {
+			rasm_error!(self.line_num, "{}", "Origin cannot be set inside a .virtual block");
+		}
+
+		if let Some(segment) = self.current_segment {
+			rasm_error!(self.line_num, "Origin cannot be set inside segment \"{}\"", self.memory_config.as_ref().unwrap().segments[segment].name);
+		}
+
+		self.program_counter = addr as usize;
+		if self.pass != Pass::Main {
+			return;
+		}
+
+		if let Some(chunk) = self.chunks.iter().find(|c| c.bank == self.current_bank && c.contains(addr)) {
+			rasm_error!(self.line_num, "Origin ${:04x} goes back over bytes already written at ${:04x}-${:04x}", addr, chunk.start, chunk.end() - 1);
+		}
+
+		let chunk = &mut self.chunks[self.current_chunk];
+		if chunk.bytes.is_empty() {
+			chunk.start = addr;
+			chunk.line_num = self.line_num;
+			chunk.bank = self.current_bank;
+		} else {
+			let mut chunk = Chunk::new(addr, self.line_num);
+			chunk.bank = self.current_bank;
+			self.chunks.push(chunk);
+			self.current_chunk = self.chunks.len() - 1;
+		}
+	}
+
+	pub fn set_bank(&mut self, bank: usize, addr: u16) {
+		if self.relocatable {
+			rasm_error!(self.line_num, "{}", "Banks cannot be used when assembling relocatable output");
+		}
+
+		self.current_bank = Some(bank);
+		self.set_origin(addr);
+	}
+
+	pub fn check_bank_reference(&self, key: &(isize, String)) {
+		let (Some(current), Some(bank)) = (self.current_bank, self.label_banks.get(key).copied()) else {
+			return;
+		};
+
+		if current != bank && !self.trampolines.contains(&key.1) {
+			rasm_warning!(self.line_num, "Reference to \"{}\" in bank {} from bank {} is not made through a declared .trampoline", key.1, bank, current);
+		}
+	}
+
+	pub fn switch_segment(&mut self, name: &str) {
+		let line_num = self.line_num;
+		if self.pseudopc.is_some() {
+			rasm_error!(line_num, "{}", "Segment cannot be changed inside a .pseudopc block");
+		}
+
+		if self.virtual_block.is_some() {
+			rasm_error!(line_num, "{}", "Segment cannot be changed inside a .virtual block");
+		}
+
+		let config = match self.memory_config.as_mut() {
+			Some(config) => config,
+			None => rasm_error!(line_num, "Segment \"{}\" used without a memory configuration (-C)", name),
+		};
+
+		let index = config.segments.iter().position(|s| s.name == name).unwrap_or_else(
+			|| rasm_error!(line_num, "Segment \"{}\" is not defined in the memory configuration", name)
+		);
+
+		if let Some(current) = self.current_segment {
+			config.segments[current].pc = self.program_counter;
+		}
+
+		self.current_segment = Some(index);
+		self.program_counter = config.segments[index].pc;
+
+		if self.pass == Pass::Main {
+			self.current_chunk = match self.chunks.iter().position(|c| c.segment == Some(index)) {
+				Some(chunk) => chunk,
+				None => {
+					let mut chunk = Chunk::new(self.program_counter as u16, self.line_num);
+					chunk.segment = Some(index);
+					self.chunks.push(chunk);
+					self.chunks.len() - 1
+				},
+			};
+		}
+	}
+
+	pub fn begin_block(&mut self) {
+		self.max_block += 1;
+		self.current_block = self.max_block;
+		if self.pass == Pass::Label {
+			self.labels.insert(self.current_block, hashmap!{});
+		}
+	}
+
+	pub fn define_label(&mut self, name: &str) {
+		let key = (self.current_block, name.to_string());
+		let current_labels = self.labels.get_mut(&self.current_block).unwrap();
+		current_labels.insert(name.into(), self.program_counter as u16);
+
+		let load_addr = self.physical_counter() as u16;
+		let load_labels = self.load_labels.entry(self.current_block).or_default();
+		load_labels.insert(name.into(), load_addr);
+
+		self.procs.define_label(key.clone());
+		self.label_lines.insert(key.clone(), (self.line_num, self.current_segment));
+		if let Some(bank) = self.current_bank {
+			self.label_banks.insert(key.clone(), bank);
+		}
+
+		if let (true, Some(segment), None) = (self.relocatable, self.current_segment, &self.virtual_block) {
+			self.label_segments.insert(key, (segment, self.pseudopc.is_none()));
+		}
+	}
+
+	pub fn begin_pseudopc(&mut self, addr: u16) {
+		if self.pseudopc.is_some() {
+			rasm_error!(self.line_num, "{}", ".pseudopc blocks cannot be nested");
+		}
+
+		self.pseudopc = Some((addr, self.program_counter));
+		self.program_counter = addr as usize;
+	}
+
+	pub fn end_pseudopc(&mut self) {
+		self.program_counter = self.physical_counter();
+		if self.pseudopc.take().is_none() {
+			rasm_error!(self.line_num, "{}", ".endpseudopc without matching .pseudopc");
+		}
+	}
+
+	pub fn begin_virtual(&mut self, addr: u16) {
+		if self.virtual_block.is_some() {
+			rasm_error!(self.line_num, "{}", ".virtual blocks cannot be nested");
+		}
+
+		self.virtual_block = Some((self.program_counter, self.pseudopc.take()));
+		self.program_counter = addr as usize;
+	}
+
+	pub fn end_virtual(&mut self) {
+		match self.virtual_block.take() {
+			Some((program_counter, pseudopc)) => {
+				self.program_counter = program_counter;
+				self.pseudopc = pseudopc;
+			},
+			None => {
+				rasm_error!(self.line_num, "{}", ".endvirtual without matching .virtual");
+			},
+		}
+	}
+
+	pub fn physical_counter(&self) -> usize {
+		match self.pseudopc {
+			Some((logical_start, physical_start)) => physical_start + (self.program_counter - logical_start as usize),
+			None => self.program_counter,
+		}
+	}
+
+	fn current_segment_kind(&self) -> Option<SegmentKind> {
+		self.current_segment.map(|s| self.memory_config.as_ref().unwrap().segments[s].kind)
+	}
+
+	fn is_zeropage_target(&self, target: &RelocTarget) -> bool {
+		match target {
+			RelocTarget::Segment(segment) => self.memory_config.as_ref().unwrap().segments[*segment].kind == SegmentKind::Zeropage,
+			RelocTarget::Import(name) => self.imports.iter().any(|i| &i.name == name && i.zeropage),
+		}
+	}
+
+	pub fn fixup_is_wide(&self) -> bool {
+		match &self.last_fixup {
+			Some(fixup) => fixup.kind == FixupKind::Full && !self.is_zeropage_target(&fixup.target),
+			None => false,
+		}
+	}
+
+	pub fn relocate(&mut self, offset: usize, width: usize) {
+		let fixup = match self.last_fixup.take() {
+			Some(fixup) => fixup,
+			None => return,
+		};
+
+		if self.pass != Pass::Main {
+			return;
+		}
+
+		let kind = match (fixup.kind, width) {
+			(FixupKind::Full, 2) => RelocKind::Word,
+			(FixupKind::Full, _) if self.is_zeropage_target(&fixup.target) => RelocKind::Zeropage,
+			(FixupKind::Full, _) => {
+				rasm_error!(self.line_num, "{}", "Relocatable address does not fit in a byte; use < or >");
+			},
+			(FixupKind::Lo, _) => RelocKind::Lo,
+			(FixupKind::Hi, _) => RelocKind::Hi,
+		};
+
+		let segment = self.current_segment.unwrap();
+		let base = self.memory_config.as_ref().unwrap().segments[segment].base as usize;
+		let offset = (self.physical_counter() - base + offset) as u16;
+		self.relocations.push(Relocation{segment, offset, kind, target: fixup.target, value: fixup.value});
+	}
+
+	pub fn emit(&mut self, bytes: &[u8]) {
+		if !bytes.is_empty() && self.virtual_block.is_some() {
+			rasm_error!(self.line_num, "{}", "Cannot emit bytes inside a .virtual block; use .res");
+		}
+
+		if !bytes.is_empty() && self.current_segment_kind().is_some_and(|k| k.is_uninitialized()) {
+			let segment = &self.memory_config.as_ref().unwrap().segments[self.current_segment.unwrap()];
+			rasm_error!(self.line_num, "Cannot store data in uninitialized segment \"{}\"; use .res", segment.name);
+		}
+
+		if !bytes.is_empty() && self.pass == Pass::Label {
+			self.procs.code_emitted();
+		}
+
+		self.line_addr.get_or_insert(self.program_counter);
+		self.program_counter += bytes.len();
+		if self.pass == Pass::Main {
+			self.chunks[self.current_chunk].bytes.extend_from_slice(bytes);
+			self.line_bytes.extend_from_slice(bytes);
+		}
+	}
+
+	fn record_line(&mut self, source: &str, listed: bool) {
+		let line_addr = self.line_addr.take().unwrap_or(self.program_counter);
+		if self.pass != Pass::Main {
+			return;
+		}
+
+		let bytes = std::mem::take(&mut self.line_bytes);
+		let cycles = self.line_cycles.take();
+		if !bytes.is_empty() {
+			let end = self.program_counter - 1;
+			let start = end + 1 - bytes.len();
+			let columns = debuginfo::source_columns(source);
+			self.line_ranges.push(LineRange{line_num: self.line_num, start: start as u16, end: end as u16, segment: self.current_segment, columns});
+		}
+
+		if let Some(listing) = self.listing.as_mut() {
+			let addr = if listed { Some(line_addr as u16) } else { None };
+			listing.push(ListingLine{line_num: self.line_num, addr, bytes, cycles, source: source.into()});
+		}
+	}
+
+	pub fn reserve(&mut self, count: usize, fill: u8) {
+		self.line_addr.get_or_insert(self.program_counter);
+		if self.virtual_block.is_some() || self.current_segment_kind().is_some_and(|k| k.is_uninitialized()) {
+			self.program_counter += count;
+		} else {
+			self.emit(&vec![fill; count]);
+		}
+	}
 }
 
 pub struct ErrorMsg {
@@ -57,28 +391,70 @@ macro_rules! rasm_error {
 		std::panic::panic_any(ErrorMsg::new(
 			format!($fmt, $($arg)*),
 			$line,
-		));
+		))
 	};
 }
 
-fn assemble(file: &File, assembly_state: &mut AssemblyState) -> (Vec<u8>, u16) {
-	let mut load_addr = 0x0801u16;
-	let mut code = vec![0, 0];
+#[macro_export]
+macro_rules! rasm_warning {
+	($line:expr, $fmt:literal, $($arg:tt)*) => {
+		if $line > 0 {
+			eprintln!("\x1b[0;93mWARNING:\x1b[0m Line {}: {}", $line, format!($fmt, $($arg)*));
+		} else {
+			eprintln!("\x1b[0;93mWARNING:\x1b[0m {}", format!($fmt, $($arg)*));
+		}
+	};
+}
+
+fn assemble(file: &File, assembly_state: &mut AssemblyState) -> Vec<Chunk> {
 	assembly_state.line_num = 1;
 	assembly_state.current_block = -1;
 	assembly_state.max_block = -1;
+	assembly_state.program_counter = DEFAULT_ORIGIN as usize;
+	assembly_state.chunks = vec![Chunk::new(DEFAULT_ORIGIN, 0)];
+	assembly_state.current_chunk = 0;
+	assembly_state.current_segment = None;
+	assembly_state.current_bank = None;
+	assembly_state.pseudopc = None;
+	assembly_state.virtual_block = None;
+	assembly_state.last_fixup = None;
+	assembly_state.procs.reset();
+	assembly_state.line_bytes.clear();
+	assembly_state.line_addr = None;
+	assembly_state.line_cycles = None;
+	if let Some(config) = assembly_state.memory_config.as_mut() {
+		config.reset();
+	}
+
+	if assembly_state.relocatable {
+		assembly_state.switch_segment("CODE");
+	}
 
 	for ln in io::BufReader::new(file).lines() {
 		let line = ln.unwrap();
 		let pretrimmed = line.trim();
 		let trimmed = {
 			if let Some(idx) = pretrimmed.find(';') {
-				&pretrimmed[0..idx].trim_end()
+				pretrimmed[0..idx].trim_end()
 			} else {
 				pretrimmed
 			}
 		};
 
+		if assembly_state.procs.skipping {
+			// Blocks inside dropped procs still count so later block numbers stay stable between passes
+			match regexes::REGEX_PSEUDO.captures(trimmed).as_ref().map(|m| &m[1]) {
+				Some("endproc") => assembly_state.procs.end(assembly_state.program_counter, assembly_state.line_num),
+				Some("block") => assembly_state.begin_block(),
+				Some("bend") => assembly_state.current_block = -1,
+				_ => {},
+			}
+
+			assembly_state.record_line(&line, false);
+			assembly_state.line_num += 1;
+			continue;
+		}
+
 		if let Some(matches) = regexes::REGEX_ASSIGN.captures(trimmed) {
 			let name = &matches[1];
 			let value_str = &matches[2];
@@ -90,32 +466,183 @@ fn assemble(file: &File, assembly_state: &mut AssemblyState) -> (Vec<u8>, u16) {
 			};
 			
 			if name == "*" {
-				load_addr = value;
-				assembly_state.program_counter = value as usize;
+				assembly_state.set_origin(value);
 			} else if assembly_state.pass == Pass::Constant {
 				assembly_state.constants.insert(name.into(), value);
 			}
 		} else if let Some(matches) = regexes::REGEX_LABEL.captures(trimmed) {
 			if assembly_state.pass == Pass::Label {
-				let current_labels = assembly_state.labels.get_mut(&assembly_state.current_block).unwrap();
-				current_labels.insert(matches[1].into(), assembly_state.program_counter as u16);
+				assembly_state.define_label(&matches[1]);
 			}
 		} else if let Some(matches) = regexes::REGEX_PSEUDO.captures(trimmed) {
-			if assembly_state.pass != Pass::Constant {
+			if assembly_state.pass == Pass::Constant {
 				match &matches[1] {
-					"block" => {
-						assembly_state.max_block += 1;
-						assembly_state.current_block = assembly_state.max_block;
-						if assembly_state.pass == Pass::Label {
-							assembly_state.labels.insert(assembly_state.current_block, hashmap!{});
+					"zp" | "bss" => {
+						let space = if &matches[1] == "zp" { VarSpace::Zeropage } else { VarSpace::Bss };
+						let args = matches[2].split(',').map(|a| a.trim()).collect::<Vec<&str>>();
+						let size = match args.get(1) {
+							Some(s) => parse_expression(s, assembly_state).unwrap_or_else(
+								|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", s)
+							),
+							None => 1,
+						};
+
+						let group = args.get(2).map(|g| g.to_string());
+						let line_num = assembly_state.line_num;
+						let addr = assembly_state.allocator.allocate(args[0], space, size, group, line_num);
+						assembly_state.constants.insert(args[0].into(), addr);
+					},
+					"import" | "importzp" => {
+						if !assembly_state.relocatable {
+							rasm_error!(assembly_state.line_num, "{}", ".import requires assembling to an object file (-c)");
+						}
+
+						let zeropage = &matches[1] == "importzp";
+						for name in matches[2].split(',').map(|n| n.trim()) {
+							if !assembly_state.imports.iter().any(|i| i.name == name) {
+								assembly_state.imports.push(Import{name: name.into(), zeropage});
+							}
+						}
+					},
+					"trampoline" => {
+						for name in matches[2].split(',').map(|n| n.trim()) {
+							if !assembly_state.trampolines.iter().any(|t| t == name) {
+								assembly_state.trampolines.push(name.into());
+							}
+						}
+					},
+					"export" => {
+						for name in matches[2].split(',').map(|n| n.trim()) {
+							if !assembly_state.exports.iter().any(|e| e == name) {
+								assembly_state.exports.push(name.into());
+							}
+						}
+					},
+					"zprange" | "bssrange" => {
+						let space = if &matches[1] == "zprange" { VarSpace::Zeropage } else { VarSpace::Bss };
+						let args = matches[2].split(',').map(|a| {
+							parse_expression(a.trim(), assembly_state).unwrap_or_else(
+								|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", a.trim())
+							)
+						}).collect::<Vec<u16>>();
+
+						if args.len() != 2 {
+							rasm_error!(assembly_state.line_num, ".{} takes a start and end address", &matches[1]);
 						}
+
+						let line_num = assembly_state.line_num;
+						assembly_state.allocator.add_range(space, args[0], args[1], line_num);
+					},
+					_ => {},
+				}
+			} else {
+				match &matches[1] {
+					"zp" | "bss" | "zprange" | "bssrange" | "import" | "importzp" | "export" | "trampoline" => {},
+					"bank" => {
+						let args = matches[2].split(',').map(|a| a.trim()).collect::<Vec<&str>>();
+						let bank = parse_expression(args[0], assembly_state).unwrap_or_else(
+							|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", args[0])
+						);
+
+						let addr = match args.get(1) {
+							Some(a) => parse_expression(a, assembly_state).unwrap_or_else(
+								|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", a)
+							),
+							None => cart::BANK_ORIGIN,
+						};
+
+						assembly_state.set_bank(bank as usize, addr);
+					},
+					"block" => {
+						assembly_state.begin_block();
 					},
 					"bend" => {
 						assembly_state.current_block = -1;
 					},
+					"pseudopc" => {
+						let addr = parse_expression(&matches[2], assembly_state).unwrap_or_else(
+							|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", &matches[2])
+						);
+
+						assembly_state.begin_pseudopc(addr);
+					},
+					"endpseudopc" => {
+						assembly_state.end_pseudopc();
+					},
+					"virtual" => {
+						let addr = parse_expression(&matches[2], assembly_state).unwrap_or_else(
+							|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", &matches[2])
+						);
+
+						assembly_state.begin_virtual(addr);
+					},
+					"endvirtual" => {
+						assembly_state.end_virtual();
+					},
+					"proc" => {
+						let name = matches[2].trim();
+						let pc = assembly_state.physical_counter();
+						let line_num = assembly_state.line_num;
+						if assembly_state.procs.begin(name, pc, line_num) && assembly_state.pass == Pass::Label {
+							assembly_state.define_label(name);
+						}
+					},
+					"endproc" => {
+						let pc = assembly_state.physical_counter();
+						let line_num = assembly_state.line_num;
+						assembly_state.procs.end(pc, line_num);
+					},
+					"break" | "watch" => {
+						let kind = if &matches[1] == "break" { BreakKind::Break } else { BreakKind::Watch };
+						let args = matches.get(2).map_or(vec![], |m| m.as_str().split(',').map(|a| a.trim()).collect::<Vec<&str>>());
+						// Breakpoints are only resolved in the main pass so they never keep a .proc alive
+						let addr = match args.first() {
+							Some(_) if assembly_state.pass != Pass::Main => None,
+							Some(a) => match parse_expression(a, assembly_state) {
+								Some(addr) => Some(addr),
+								None if assembly_state.procs.is_dead(&(assembly_state.current_block, a.to_string())) => {
+									rasm_warning!(assembly_state.line_num, "Ignoring .{} on \"{}\" in a removed procedure", &matches[1], a);
+									None
+								},
+								None => rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", a),
+							},
+							None => Some(assembly_state.program_counter as u16),
+						};
+
+						let mode = match args.get(1).map(|m| m.to_lowercase()) {
+							Some(m) if m == "load" || m == "store" || m == "exec" => Some(m),
+							Some(m) => rasm_error!(assembly_state.line_num, "Invalid .{} mode \"{}\"", &matches[1], m),
+							None => None,
+						};
+
+						if let (Pass::Main, Some(addr)) = (&assembly_state.pass, addr) {
+							let segment = assembly_state.current_segment;
+							assembly_state.breakpoints.push(Breakpoint{kind, addr, mode, segment});
+						}
+					},
+					"segment" => {
+						assembly_state.switch_segment(matches[2].trim_matches('"'));
+					},
+					"res" => {
+						let mut split = matches[2].splitn(2, ',');
+						let count_str = split.next().unwrap().trim();
+						let count = parse_expression(count_str, assembly_state).unwrap_or_else(
+							|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", count_str)
+						);
+
+						let fill = match split.next() {
+							Some(f) => parse_expression(f.trim(), assembly_state).unwrap_or_else(
+								|| rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", f.trim())
+							) as u8,
+							None => 0,
+						};
+
+						assembly_state.reserve(count as usize, fill);
+					},
 					"byte" => {
-						let bytes = matches[2].split(',').map(|b| {
+						let bytes = matches[2].split(',').enumerate().map(|(i, b)| {
 							let byte = parse_expression(b.trim(), assembly_state);
+							assembly_state.relocate(i, 1);
 							match byte {
 								Some(b) => b as u8,
 								None => {
@@ -128,14 +655,12 @@ fn assemble(file: &File, assembly_state: &mut AssemblyState) -> (Vec<u8>, u16) {
 							}
 						}).collect::<Vec<u8>>();
 						
-						assembly_state.program_counter += bytes.len();
-						if assembly_state.pass == Pass::Main {
-							code.extend(bytes);
-						}
+						assembly_state.emit(&bytes);
 					},
 					"word" => {
-						let words = matches[2].split(',').map(|w| {
+						let words = matches[2].split(',').enumerate().map(|(i, w)| {
 							let word = parse_expression(w.trim(), assembly_state);
+							assembly_state.relocate(2 * i, 2);
 							match word {
 								Some(w) => w,
 								None => {
@@ -148,13 +673,95 @@ fn assemble(file: &File, assembly_state: &mut AssemblyState) -> (Vec<u8>, u16) {
 							}
 						}).collect::<Vec<u16>>();
 
-						assembly_state.program_counter += 2 * words.len();
-						if assembly_state.pass == Pass::Main {
-							code.extend(words.iter().fold(vec![], |mut vec, w| { vec.extend(vec![lo8(*w), hi8(*w)]); vec }));
+						assembly_state.emit(&words.iter().fold(vec![], |mut vec, w| { vec.extend(vec![lo8(*w), hi8(*w)]); vec }));
+					},
+					"cbm80" => {
+						if assembly_state.pass == Pass::Main && assembly_state.program_counter != cart::CBM80_ADDR as usize {
+							rasm_error!(assembly_state.line_num, "The CBM80 signature must be placed at ${:04x}", cart::CBM80_ADDR);
 						}
+
+						let args = matches[2].split(',').map(|a| a.trim()).collect::<Vec<&str>>();
+						let vectors = args.iter().take(2).enumerate().map(|(i, v)| {
+							let vector = parse_expression(v, assembly_state);
+							assembly_state.relocate(2 * i, 2);
+							vector.unwrap_or_else(|| {
+								if assembly_state.pass == Pass::Main {
+									rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", v);
+								}
+
+								0
+							})
+						}).collect::<Vec<u16>>();
+
+						let cold = vectors[0];
+						let warm = vectors.get(1).copied().unwrap_or(cold);
+						let mut bytes = vec![lo8(cold), hi8(cold), lo8(warm), hi8(warm)];
+						bytes.extend_from_slice(&cart::CBM80_SIGNATURE);
+						assembly_state.emit(&bytes);
+					},
+					"basic" => {
+						let args = matches[2].split(',').map(|a| a.trim()).collect::<Vec<&str>>();
+						let path = args[0].trim_matches('"');
+						let version = args.get(1).map_or(BasicVersion::V2, |v| BasicVersion::from_string(v));
+						let source = fs::read_to_string(path).unwrap_or_else(
+							|_| rasm_error!(assembly_state.line_num, "Failed to open BASIC file {}", path)
+						);
+
+						let start = assembly_state.program_counter as u16;
+						let line_num = assembly_state.line_num;
+						let program = basic::tokenize(&source, start, version, line_num, &mut |name| {
+							let value = parse_expression(name, assembly_state);
+							if assembly_state.last_fixup.is_some() {
+								rasm_error!(line_num, "Cannot use relocatable symbol \"{}\" in a BASIC program", name);
+							}
+
+							if value.is_none() && assembly_state.pass == Pass::Label {
+								Some(0)
+							} else {
+								value
+							}
+						});
+
+						assembly_state.emit(&program);
+					},
+					"basicupstart" => {
+						let basic_start = assembly_state.target.basic_start();
+						match assembly_state.current_segment {
+							None => assembly_state.set_origin(basic_start),
+							Some(_) if assembly_state.relocatable => {
+								rasm_error!(assembly_state.line_num, "Cannot use .basicupstart in relocatable code; place the segment at the BASIC start ${:04x} and assemble with -C instead", basic_start);
+							},
+							Some(segment) => {
+								let pc = assembly_state.physical_counter();
+								if assembly_state.pass == Pass::Main && pc != basic_start as usize {
+									let name = &assembly_state.memory_config.as_ref().unwrap().segments[segment].name;
+									rasm_error!(assembly_state.line_num, ".basicupstart in segment \"{}\" is at ${:04x} instead of the BASIC start ${:04x}", name, pc, basic_start);
+								}
+							},
+						}
+
+						let addr = parse_expression(&matches[2], assembly_state);
+						if assembly_state.last_fixup.is_some() {
+							rasm_error!(assembly_state.line_num, "Cannot use relocatable symbol \"{}\" in .basicupstart", &matches[2]);
+						}
+
+						let addr = addr.unwrap_or_else(|| {
+							if assembly_state.pass == Pass::Main {
+								rasm_error!(assembly_state.line_num, "Undefined symbol \"{}\"", &matches[2]);
+							}
+
+							0
+						});
+
+						assembly_state.emit(&basic::upstart_stub(basic_start, addr));
 					},
 					"addrstring" => {
-						let bytes = match parse_expression(&matches[2], assembly_state) {
+						let value = parse_expression(&matches[2], assembly_state);
+						if assembly_state.last_fixup.is_some() {
+							rasm_error!(assembly_state.line_num, "Cannot use relocatable symbol \"{}\" in .addrstring", &matches[2]);
+						}
+
+						let bytes = match value {
 							Some(v) => {
 								let mut vec = vec![];
 								let string = v.to_string();
@@ -174,36 +781,24 @@ fn assemble(file: &File, assembly_state: &mut AssemblyState) -> (Vec<u8>, u16) {
 							},
 						};
 
-						assembly_state.program_counter += bytes.len();
-						if assembly_state.pass == Pass::Main {
-							code.extend(bytes);
-						}
+						assembly_state.emit(&bytes);
 					},
 					"string" => {
 						let text = matches[2].trim_matches('"');
 						let vec = text.chars().map(|c| char_format(c as u8, &assembly_state.target)).collect::<Vec<u8>>();
-						assembly_state.program_counter += vec.len();
-						if assembly_state.pass == Pass::Main {
-							code.extend(vec);
-						}
+						assembly_state.emit(&vec);
 					},
 					"cstring" => {
 						let text = matches[2].trim_matches('"');
-						let vec = text.chars().map(|c| char_format(c as u8, &assembly_state.target)).collect::<Vec<u8>>();
-						assembly_state.program_counter += vec.len() + 1;
-						if assembly_state.pass == Pass::Main {
-							code.extend(vec);
-							code.push(0);
-						}
+						let mut vec = text.chars().map(|c| char_format(c as u8, &assembly_state.target)).collect::<Vec<u8>>();
+						vec.push(0);
+						assembly_state.emit(&vec);
 					},
 					"cbmstring" => {
 						let text = matches[2].trim_matches('"');
 						let mut chars = text.chars().map(|c| char_format(c as u8, &assembly_state.target)).collect::<Vec<u8>>();
 						*chars.last_mut().unwrap() |= 0x80;
-						assembly_state.program_counter += chars.len();
-						if assembly_state.pass == Pass::Main {
-							code.extend(chars);
-						}
+						assembly_state.emit(&chars);
 					},
 					_ => {
 						rasm_error!(assembly_state.line_num, "Invalid pseudo-op \"{}\"", &matches[1]);
@@ -216,19 +811,36 @@ fn assemble(file: &File, assembly_state: &mut AssemblyState) -> (Vec<u8>, u16) {
 				let operand = &matches.get(2).map_or("", |m| m.as_str());
 
 				let bytes = get_instruction_bytes(mnemonic, operand, assembly_state);
-				assembly_state.program_counter += bytes.len();
-				if assembly_state.pass == Pass::Main {
-					code.extend(bytes);
-				}
+				assembly_state.line_cycles = Some(CYCLES[bytes[0] as usize]);
+				assembly_state.emit(&bytes);
 			}
 		} else if !trimmed.is_empty() {
 			rasm_error!(assembly_state.line_num, "Invalid syntax \"{}\"", trimmed);
 		}
 
+		let listed = !assembly_state.procs.skipping;
+		assembly_state.record_line(&line, listed);
+
 		assembly_state.line_num += 1;
 	}
 
-	(code, load_addr)
+	if assembly_state.pseudopc.is_some() {
+		rasm_error!(assembly_state.line_num - 1, "{}", "Unterminated .pseudopc block");
+	}
+
+	if assembly_state.virtual_block.is_some() {
+		rasm_error!(assembly_state.line_num - 1, "{}", "Unterminated .virtual block");
+	}
+
+	if let Some(config) = assembly_state.memory_config.as_mut() {
+		if let Some(current) = assembly_state.current_segment {
+			config.segments[current].pc = assembly_state.program_counter;
+		}
+
+		config.record_sizes();
+	}
+
+	std::mem::take(&mut assembly_state.chunks)
 }
 
 fn main() {
@@ -248,12 +860,34 @@ fn main() {
 
 	let mut target = Target::C64;
 
-	let mut input_file = String::new();
+	let mut input_files = Vec::<String>::new();
 	let mut output_file = String::new();
-	let mut input_file_given = false;
+	let mut config_file = None;
+	let mut object_output = false;
+	let mut format = OutputFormat::Prg;
+	let mut map_file = None;
+	let mut listing_file = None;
+	let mut vice_file = None;
+	let mut debug_file = None;
+	let mut report_file = None;
+	let mut options = OutputOptions{
+		pad: None, fill: 0, name: String::new(), symbols: vec![],
+		disk_name: None, disk_files: vec![], cartridge: CartType::Normal8k, crunch: None,
+	};
 
 	let mut args = env::args().peekable();
 	args.next().unwrap();
+	if args.next_if(|a| a == "detok").is_some() {
+		basic::detokenize_command(args.collect());
+		return;
+	}
+
+	if args.next_if(|a| a == "lib").is_some() {
+		library::library_command(args.collect());
+		return;
+	}
+
+	let link_mode = args.next_if(|a| a == "link").is_some();
 	while let Some(arg) = args.next() {
 		match arg.as_str() {
 			"-o" => {
@@ -263,6 +897,92 @@ fn main() {
 				
 				args.next().unwrap();
 			},
+			"-c" => {
+				object_output = true;
+			},
+			"-f" => {
+				format = OutputFormat::from_string(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid output format specified")
+				));
+				args.next().unwrap();
+			},
+			"-pad" => {
+				let size = args.peek().unwrap_or_else(|| rasm_error!(0, "{}", "No valid padding size specified"));
+				options.pad = Some(parse_size(size).unwrap_or_else(|| rasm_error!(0, "Invalid padding size \"{}\"", size)));
+				args.next().unwrap();
+			},
+			"-fill" => {
+				let fill = args.peek().unwrap_or_else(|| rasm_error!(0, "{}", "No valid fill byte specified"));
+				options.fill = parse_num(fill).filter(|f| *f <= 0xff).unwrap_or_else(
+					|| rasm_error!(0, "Invalid fill byte \"{}\"", fill)
+				) as u8;
+				args.next().unwrap();
+			},
+			"-sym" => {
+				let names = args.peek().unwrap_or_else(|| rasm_error!(0, "{}", "No valid symbol list specified"));
+				options.symbols.extend(names.split(',').map(|n| n.trim().to_string()));
+				args.next().unwrap();
+			},
+			"-diskname" => {
+				options.disk_name = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid disk name specified")
+				).to_string());
+				args.next().unwrap();
+			},
+			"-file" => {
+				options.disk_files.push(DiskFile::from_arg(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid disk file specified")
+				)));
+				args.next().unwrap();
+			},
+			"-cart" => {
+				options.cartridge = CartType::from_string(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid cartridge type specified")
+				));
+				args.next().unwrap();
+			},
+			"-crunch" => {
+				options.crunch = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid crunch entry point specified")
+				).to_string());
+				args.next().unwrap();
+			},
+			"-l" => {
+				listing_file = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid listing file specified")
+				).to_string());
+				args.next().unwrap();
+			},
+			"-vs" => {
+				vice_file = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid VICE label file specified")
+				).to_string());
+				args.next().unwrap();
+			},
+			"-dbg" => {
+				debug_file = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid debug info file specified")
+				).to_string());
+				args.next().unwrap();
+			},
+			"-json" => {
+				report_file = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid report file specified")
+				).to_string());
+				args.next().unwrap();
+			},
+			"-m" => {
+				map_file = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid map file specified")
+				).to_string());
+				args.next().unwrap();
+			},
+			"-C" => {
+				config_file = Some(args.peek().unwrap_or_else(
+					|| rasm_error!(0, "{}", "No valid memory configuration file specified")
+				).to_string());
+				args.next().unwrap();
+			},
 			"-t" => {
 				target = Target::from_string(args.peek().unwrap_or_else(
 					|| rasm_error!(0, "{}", "No valid target specified")
@@ -270,41 +990,142 @@ fn main() {
 				args.next().unwrap();
 			},
 			_ => {
-				input_file = arg.to_string();
-				if output_file.is_empty() {
-					let file = arg.to_string();
-					output_file = match file.find('.') {
-						Some(pos) => {
-							String::from(&file[..pos]) + ".prg".into()
-						},
-						None => {
-							file + ".prg".into()
-						},
-					}
-				}
-
-				input_file_given = true;
+				input_files.push(arg.to_string());
 			},
 		}
 	}
 
-	if !input_file_given {
+	if input_files.is_empty() {
 		rasm_error!(0, "{}", "No input file specified");
 	}
 
-	let mut infile = File::open(&input_file).unwrap_or_else(
-		|_| rasm_error!(0, "Failed to open input file {}", &input_file)
+	if !link_mode && input_files.len() > 1 {
+		rasm_error!(0, "{}", "Only one input file can be assembled at a time; use \"rasm link\" to combine object files");
+	}
+
+	if link_mode && format.is_relocatable() {
+		rasm_error!(0, "{}", "Linked output cannot be written in a relocatable format");
+	}
+
+	let relocatable = object_output || format.is_relocatable();
+	if link_mode && listing_file.is_some() {
+		rasm_error!(0, "{}", "A listing file cannot be written when linking; write listings when assembling the object files");
+	}
+
+	if relocatable && vice_file.is_some() {
+		rasm_error!(0, "{}", "VICE labels cannot be written for relocatable output; write them when linking");
+	}
+
+	if (relocatable || link_mode) && debug_file.is_some() {
+		rasm_error!(0, "{}", "Debug info can only be written when assembling a program directly, not for relocatable or linked output");
+	}
+
+	if (relocatable || link_mode) && report_file.is_some() {
+		rasm_error!(0, "{}", "A build report can only be written when assembling a program directly, not for relocatable or linked output");
+	}
+
+	if output_file.is_empty() {
+		let extension = if object_output { ".o" } else { format.extension() };
+		let file = input_files[0].clone();
+		output_file = match file.find('.') {
+			Some(pos) => {
+				String::from(&file[..pos]) + extension
+			},
+			None => {
+				file + extension
+			},
+		}
+	}
+
+	options.name = Path::new(&output_file).file_stem().map_or(String::from("rasm"), |s| s.to_string_lossy().to_string());
+
+	let memory_config = match config_file {
+		Some(path) => {
+			let text = fs::read_to_string(&path).unwrap_or_else(
+				|_| rasm_error!(0, "Failed to open memory configuration file {}", &path)
+			);
+
+			Some(MemoryConfig::parse(&text))
+		},
+		None if link_mode || relocatable => Some(MemoryConfig::parse(target.default_memory_config())),
+		None => None,
+	};
+
+	if link_mode {
+		let mut objects = Vec::<ObjectFile>::new();
+		let mut libraries = Vec::<Library>::new();
+		for path in input_files.iter() {
+			let bytes = fs::read(path).unwrap_or_else(
+				|_| rasm_error!(0, "Failed to open object file {}", path)
+			);
+
+			if Library::is_library(&bytes) {
+				libraries.push(Library::from_bytes(&bytes, path));
+			} else {
+				objects.push(ObjectFile::from_bytes(&bytes, path));
+			}
+		}
+
+		let objects = library::select_members(objects, libraries);
+
+		let mut config = memory_config.unwrap();
+		let (mut chunks, symbols) = linker::link(&objects, &mut config);
+		config.apply_fill(&mut chunks);
+		output::check_overlaps(&chunks);
+		if format.is_loaded() {
+			output::check_regions(&chunks, &target);
+		}
+
+		if let Some(path) = map_file {
+			fs::write(&path, mapfile::write_map(&target, &chunks, Some(&config), &symbols)).unwrap_or_else(
+				|_| rasm_error!(0, "Failed to write to map file {}", &path)
+			);
+		}
+
+		if let Some(path) = vice_file {
+			fs::write(&path, vice::write_vice_labels(&symbols, &[])).unwrap_or_else(
+				|_| rasm_error!(0, "Failed to write to VICE label file {}", &path)
+			);
+		}
+
+		fs::write(&output_file, output::output_image(format, &chunks, &options, &symbols)).unwrap_or_else(
+			|_| rasm_error!(0, "Failed to write to output file {}", &output_file)
+		);
+
+		return;
+	}
+
+	let input_file = &input_files[0];
+	let mut infile = File::open(input_file).unwrap_or_else(
+		|_| rasm_error!(0, "Failed to open input file {}", input_file)
 	);
 
+	let mut memory_config = memory_config;
+	if relocatable {
+		memory_config.as_mut().unwrap().make_relocatable();
+	}
+
 	let constants = HashMap::<String, u16>::new();
 	let mut labels = HashMap::<isize, HashMap<String, u16>>::new();
 	labels.insert(-1, hashmap!{});
 
+	let allocator = Allocator::new(&target);
 	let mut assembly_state = AssemblyState{
 		target, pass: Pass::Constant,
 		line_num: 1, program_counter: 0,
 		current_block: -1, max_block: -1,
-		constants, labels,
+		constants, labels, load_labels: hashmap!{},
+		chunks: vec![], current_chunk: 0,
+		memory_config, current_segment: None,
+		allocator,
+		pseudopc: None, virtual_block: None,
+		relocatable,
+		label_segments: hashmap!{}, imports: vec![], exports: vec![],
+		relocations: vec![], last_fixup: None, value_target: None,
+		procs: ProcTracker::default(),
+		listing: listing_file.as_ref().map(|_| vec![]), line_bytes: vec![], line_cycles: None, line_addr: None,
+		breakpoints: vec![], line_ranges: vec![], label_lines: hashmap!{},
+		current_bank: None, label_banks: hashmap!{}, trampolines: vec![],
 	};
 
 	assemble(&infile, &mut assembly_state);
@@ -312,12 +1133,94 @@ fn main() {
 	assembly_state.pass = Pass::Label;
 	assemble(&infile, &mut assembly_state);
 	infile.seek(SeekFrom::Start(0)).unwrap_or_else(|_| rasm_error!(0, "{}", "Error occurred while parsing file"));
+	if assembly_state.procs.analyze(&assembly_state.exports, options.crunch.as_deref()) {
+		for (block, name) in assembly_state.procs.dead_labels() {
+			if let Some(labels) = assembly_state.labels.get_mut(&block) {
+				labels.remove(&name);
+			}
+		}
+
+		assemble(&infile, &mut assembly_state);
+		infile.seek(SeekFrom::Start(0)).unwrap_or_else(|_| rasm_error!(0, "{}", "Error occurred while parsing file"));
+	}
+
+	if let (Some(config), false) = (assembly_state.memory_config.as_mut(), relocatable) {
+		config.layout();
+		assemble(&infile, &mut assembly_state);
+		infile.seek(SeekFrom::Start(0)).unwrap_or_else(|_| rasm_error!(0, "{}", "Error occurred while parsing file"));
+	}
+
 	assembly_state.pass = Pass::Main;
-	let (mut code, load_addr) = assemble(&infile, &mut assembly_state);
+	let mut chunks = assemble(&infile, &mut assembly_state);
+	if let Some(path) = listing_file {
+		let symbols = symbols::collect_symbols(&assembly_state);
+		fs::write(&path, listing::write_listing(assembly_state.listing.as_ref().unwrap(), &symbols)).unwrap_or_else(
+			|_| rasm_error!(0, "Failed to write to listing file {}", &path)
+		);
+	}
+
+	if relocatable {
+		let object = ObjectFile::from_assembly(input_file, &assembly_state, &chunks);
+		let bytes = if object_output { object.to_bytes() } else { o65::write_o65(&object) };
+		fs::write(&output_file, bytes).unwrap_or_else(
+			|_| rasm_error!(0, "Failed to write to output file {}", &output_file)
+		);
+
+		print!("{}", assembly_state.allocator.report());
+		print!("{}", assembly_state.procs.report());
+		return;
+	}
+
+	if let Some(config) = assembly_state.memory_config.as_mut() {
+		config.layout();
+		config.apply_fill(&mut chunks);
+	}
+
+	output::check_overlaps(&chunks);
+	if !format.is_cartridge() {
+		if let Some(chunk) = chunks.iter().find(|c| !c.bytes.is_empty() && c.bank.is_some()) {
+			rasm_error!(chunk.line_num, "{}", "Banked code can only be written to a cartridge (-f crt)");
+		}
+	}
+
+	if format.is_loaded() {
+		output::check_regions(&chunks, &assembly_state.target);
+	}
+
+	if let Some(path) = vice_file {
+		let symbols = symbols::collect_symbols(&assembly_state);
+		fs::write(&path, vice::write_vice_labels(&symbols, &assembly_state.breakpoints)).unwrap_or_else(
+			|_| rasm_error!(0, "Failed to write to VICE label file {}", &path)
+		);
+	}
+
+	if let Some(path) = debug_file {
+		let symbols = symbols::collect_symbols(&assembly_state);
+		let source = fs::canonicalize(input_file).map_or(input_file.to_string(), |p| p.display().to_string());
+		let info = debuginfo::write_c64debugger(&source, &assembly_state, &symbols);
+		fs::write(&path, info).unwrap_or_else(|_| rasm_error!(0, "Failed to write to debug info file {}", &path));
+	}
 
-	code[0] = lo8(load_addr);
-	code[1] = hi8(load_addr);
+	if let Some(path) = report_file {
+		let symbols = symbols::collect_symbols(&assembly_state);
+		let report = report::write_report(input_file, &assembly_state, &chunks, &symbols);
+		fs::write(&path, report).unwrap_or_else(|_| rasm_error!(0, "Failed to write to report file {}", &path));
+	}
+
+	if let Some(path) = map_file {
+		let symbols = symbols::collect_symbols(&assembly_state);
+		let map = mapfile::write_map(&assembly_state.target, &chunks, assembly_state.memory_config.as_ref(), &symbols);
+		fs::write(&path, map).unwrap_or_else(
+			|_| rasm_error!(0, "Failed to write to map file {}", &path)
+		);
+	}
+
+	let symbols = symbols::collect_symbols(&assembly_state);
+	let code = output::output_image(format, &chunks, &options, &symbols);
 	fs::write(&output_file, code).unwrap_or_else(
 		|_| rasm_error!(0, "Failed to write to output file {}", &output_file)
 	);
+
+	print!("{}", assembly_state.allocator.report());
+	print!("{}", assembly_state.procs.report());
 }
@@ -0,0 +1,68 @@
+use std::fmt::Write;
+
+use crate::output::Chunk;
+use crate::segments::MemoryConfig;
+use crate::symbols::Symbol;
+use crate::target::Target;
+
+fn chunk_name(chunk: &Chunk, config: Option<&MemoryConfig>) -> String {
+	match (chunk.segment, config) {
+		(Some(segment), Some(config)) => config.segments[segment].name.clone(),
+		_ if chunk.fill => "(fill)".into(),
+		_ => "(origin)".into(),
+	}
+}
+
+pub fn write_map(target: &Target, chunks: &[Chunk], config: Option<&MemoryConfig>, symbols: &[Symbol]) -> String {
+	let mut map = String::new();
+	writeln!(map, "Memory map for target {}", target.name()).unwrap();
+
+	let mut sorted = chunks.iter().filter(|c| !c.bytes.is_empty()).collect::<Vec<&Chunk>>();
+	sorted.sort_by_key(|c| c.start);
+
+	let mut used = vec![false; 0x10000];
+	for chunk in sorted.iter() {
+		used[chunk.start as usize..chunk.end()].iter_mut().for_each(|u| *u = true);
+	}
+
+	writeln!(map, "\nSegments:").unwrap();
+	writeln!(map, "  {:<16} {:<6} {:<6} Size", "Name", "Start", "End").unwrap();
+	for chunk in sorted.iter() {
+		writeln!(map, "  {:<16} ${:04x}  ${:04x}  ${:04x}", chunk_name(chunk, config), chunk.start, chunk.end() - 1, chunk.bytes.len()).unwrap();
+	}
+
+	if let Some(config) = config {
+		for segment in config.segments.iter().filter(|s| s.kind.is_uninitialized() && s.size > 0) {
+			let end = segment.base as usize + segment.size;
+			writeln!(map, "  {:<16} ${:04x}  ${:04x}  ${:04x}  (uninitialized)", segment.name, segment.base, end - 1, segment.size).unwrap();
+			used[segment.base as usize..end].iter_mut().for_each(|u| *u = true);
+		}
+	}
+
+	writeln!(map, "\nFree gaps:").unwrap();
+	for pair in sorted.windows(2) {
+		if pair[0].end() < pair[1].start as usize {
+			let size = pair[1].start as usize - pair[0].end();
+			writeln!(map, "  ${:04x}-${:04x}  ({} bytes)", pair[0].end(), pair[1].start - 1, size).unwrap();
+		}
+	}
+
+	writeln!(map, "\nSymbols:").unwrap();
+	for symbol in symbols.iter() {
+		writeln!(map, "  ${:04x}  {:<32} {}", symbol.value, symbol.scoped_name(), symbol.kind.name()).unwrap();
+	}
+
+	writeln!(map, "\nMemory areas:").unwrap();
+	writeln!(map, "  {:<16} {:<11} {:>6} {:>6}", "Name", "Range", "Used", "Free").unwrap();
+	let (mut total_used, mut total_free) = (0, 0);
+	for region in target.memory_regions() {
+		let count = used[region.start as usize..=region.end as usize].iter().filter(|u| **u).count();
+		let free = region.end as usize - region.start as usize + 1 - count;
+		total_used += count;
+		total_free += free;
+		writeln!(map, "  {:<16} ${:04x}-${:04x} {:>6} {:>6}", region.name, region.start, region.end, count, free).unwrap();
+	}
+
+	writeln!(map, "  {:<16} {:<11} {:>6} {:>6}", "Total", "", total_used, total_free).unwrap();
+	map
+}
@@ -0,0 +1,239 @@
+use crate::object::{ObjectFile, RelocKind, RelocTarget, push_u16};
+use crate::segments::SegmentKind;
+use crate::utility::*;
+use crate::{ErrorMsg, rasm_error};
+
+const SEG_UNDEFINED: u8 = 0;
+const SEG_ABSOLUTE: u8 = 1;
+const SEG_TEXT: u8 = 2;
+const SEG_DATA: u8 = 3;
+const SEG_BSS: u8 = 4;
+const SEG_ZEROPAGE: u8 = 5;
+
+const RELOC_WORD: u8 = 0x80;
+const RELOC_HIGH: u8 = 0x40;
+const RELOC_LOW: u8 = 0x20;
+
+const MODE_OBJECT: u16 = 0x1000;
+
+fn segment_id(kind: SegmentKind) -> u8 {
+	match kind {
+		SegmentKind::ReadOnly => SEG_TEXT,
+		SegmentKind::ReadWrite => SEG_DATA,
+		SegmentKind::Bss => SEG_BSS,
+		SegmentKind::Zeropage => SEG_ZEROPAGE,
+	}
+}
+
+struct RelocEntry {
+	position: usize,
+	kind: u8,
+	segment: u8,
+	undefined_index: u16,
+	low_byte: u8,
+}
+
+fn write_relocations(bytes: &mut Vec<u8>, mut entries: Vec<RelocEntry>) {
+	entries.sort_by_key(|e| e.position);
+
+	let mut last = -1isize;
+	for entry in entries {
+		let mut distance = entry.position as isize - last;
+		while distance > 254 {
+			bytes.push(255);
+			distance -= 254;
+		}
+
+		bytes.push(distance as u8);
+		bytes.push(entry.kind | entry.segment);
+		if entry.segment == SEG_UNDEFINED {
+			push_u16(bytes, entry.undefined_index);
+		}
+
+		if entry.kind == RELOC_HIGH {
+			bytes.push(entry.low_byte);
+		}
+
+		last = entry.position as isize;
+	}
+
+	bytes.push(0);
+}
+
+pub fn write_o65(object: &ObjectFile) -> Vec<u8> {
+	// Place each rasm segment inside the o65 segment matching its type
+	let mut lengths = [0usize; 6];
+	let placement = object.segments.iter().map(|segment| {
+		let id = segment_id(segment.kind);
+		let offset = lengths[id as usize];
+		lengths[id as usize] += segment.size as usize;
+		(id, offset)
+	}).collect::<Vec<(u8, usize)>>();
+
+	let mut text = vec![];
+	let mut data = vec![];
+	for (segment, &(id, _)) in object.segments.iter().zip(placement.iter()) {
+		match id {
+			SEG_TEXT => text.extend(&segment.data),
+			SEG_DATA => data.extend(&segment.data),
+			_ => {},
+		}
+	}
+
+	let undefined = object.imports.iter().map(|i| i.name.clone()).collect::<Vec<String>>();
+	let mut text_relocs = vec![];
+	let mut data_relocs = vec![];
+
+	for reloc in object.relocations.iter() {
+		let (id, offset) = placement[reloc.segment];
+		let position = offset + reloc.offset as usize;
+
+		let (segment, undefined_index, value) = match &reloc.target {
+			RelocTarget::Segment(target) => {
+				let (target_id, target_offset) = placement[*target];
+				(target_id, 0, reloc.value.wrapping_add(target_offset as u16))
+			},
+			RelocTarget::Import(name) => {
+				(SEG_UNDEFINED, undefined.iter().position(|u| u == name).unwrap() as u16, reloc.value)
+			},
+		};
+
+		let bytes = if id == SEG_TEXT { &mut text } else { &mut data };
+		let kind = match reloc.kind {
+			RelocKind::Word => {
+				bytes[position] = lo8(value);
+				bytes[position + 1] = hi8(value);
+				RELOC_WORD
+			},
+			RelocKind::Lo | RelocKind::Zeropage => {
+				bytes[position] = lo8(value);
+				RELOC_LOW
+			},
+			RelocKind::Hi => {
+				bytes[position] = hi8(value);
+				RELOC_HIGH
+			},
+		};
+
+		let entry = RelocEntry{position, kind, segment, undefined_index, low_byte: lo8(value)};
+		match id {
+			SEG_TEXT => text_relocs.push(entry),
+			SEG_DATA => data_relocs.push(entry),
+			_ => rasm_error!(0, "{}", "Relocations cannot be stored in bss or zero page segments"),
+		}
+	}
+
+	let mode = if undefined.is_empty() { 0 } else { MODE_OBJECT };
+
+	let mut bytes = vec![0x01, 0x00, b'o', b'6', b'5', 0x00];
+	push_u16(&mut bytes, mode);
+	for id in [SEG_TEXT, SEG_DATA, SEG_BSS, SEG_ZEROPAGE] {
+		if lengths[id as usize] > u16::MAX as usize {
+			rasm_error!(0, "{}", "o65 segment is larger than 64K");
+		}
+
+		push_u16(&mut bytes, 0);
+		push_u16(&mut bytes, lengths[id as usize] as u16);
+	}
+
+	push_u16(&mut bytes, 0);
+
+	let assembler = b"rasm\0";
+	bytes.push(assembler.len() as u8 + 2);
+	bytes.push(2);
+	bytes.extend(assembler);
+	bytes.push(0);
+
+	bytes.extend(text);
+	bytes.extend(data);
+
+	push_u16(&mut bytes, undefined.len() as u16);
+	for name in undefined.iter() {
+		bytes.extend(name.bytes());
+		bytes.push(0);
+	}
+
+	write_relocations(&mut bytes, text_relocs);
+	write_relocations(&mut bytes, data_relocs);
+
+	push_u16(&mut bytes, object.exports.len() as u16);
+	for export in object.exports.iter() {
+		bytes.extend(export.name.bytes());
+		bytes.push(0);
+		match export.segment {
+			Some(segment) => {
+				let (id, offset) = placement[segment];
+				bytes.push(id);
+				push_u16(&mut bytes, export.value.wrapping_add(offset as u16));
+			},
+			None => {
+				bytes.push(SEG_ABSOLUTE);
+				push_u16(&mut bytes, export.value);
+			},
+		}
+	}
+
+	bytes
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::object::{Export, Import, ObjectSegment, Relocation};
+
+	fn segment(name: &str, kind: SegmentKind, data: Vec<u8>) -> ObjectSegment {
+		ObjectSegment{name: name.into(), kind, size: data.len() as u16, data}
+	}
+
+	fn reloc(segment: usize, offset: u16, kind: RelocKind, target: RelocTarget, value: u16) -> Relocation {
+		Relocation{segment, offset, kind, target, value}
+	}
+
+	#[test]
+	fn header_and_relocation_tables() {
+		// jsr ext; lda table; rts; lda #>table+$134 / table: .word start+5
+		let object = ObjectFile{
+			name: "test.o".into(),
+			segments: vec![
+				segment("CODE", SegmentKind::ReadOnly, vec![0x20, 0, 0, 0xad, 0, 0, 0x60, 0xa9, 0]),
+				segment("DATA", SegmentKind::ReadWrite, vec![0, 0]),
+				segment("BSS", SegmentKind::Bss, vec![]),
+			],
+			relocations: vec![
+				reloc(0, 1, RelocKind::Word, RelocTarget::Import("ext".into()), 0),
+				reloc(0, 4, RelocKind::Word, RelocTarget::Segment(1), 0),
+				reloc(0, 8, RelocKind::Hi, RelocTarget::Segment(1), 0x134),
+				reloc(1, 0, RelocKind::Word, RelocTarget::Segment(0), 5),
+			],
+			imports: vec![Import{name: "ext".into(), zeropage: false}],
+			exports: vec![
+				Export{name: "start".into(), segment: Some(0), value: 0},
+				Export{name: "table".into(), segment: Some(1), value: 0},
+			],
+		};
+
+		let mut expected = vec![0x01, 0x00, b'o', b'6', b'5', 0x00, 0x00, 0x10];
+		expected.extend([0, 0, 9, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+		expected.extend([7, 2, b'r', b'a', b's', b'm', 0, 0]);
+		expected.extend([0x20, 0, 0, 0xad, 0, 0, 0x60, 0xa9, 0x01]);
+		expected.extend([0x05, 0x00]);
+		expected.extend([1, 0, b'e', b'x', b't', 0]);
+		expected.extend([2, 0x80, 0, 0, 3, 0x83, 4, 0x43, 0x34, 0]);
+		expected.extend([1, 0x82, 0]);
+		expected.extend([2, 0, b's', b't', b'a', b'r', b't', 0, SEG_TEXT, 0, 0, b't', b'a', b'b', b'l', b'e', 0, SEG_DATA, 0, 0]);
+
+		assert_eq!(write_o65(&object), expected);
+	}
+
+	#[test]
+	fn long_relocation_distances() {
+		let mut entries = vec![];
+		for position in [0, 300] {
+			entries.push(RelocEntry{position, kind: RELOC_WORD, segment: SEG_TEXT, undefined_index: 0, low_byte: 0});
+		}
+
+		let mut bytes = vec![];
+		write_relocations(&mut bytes, entries);
+		assert_eq!(bytes, vec![1, 0x82, 255, 46, 0x82, 0]);
+	}
+}
@@ -0,0 +1,316 @@
+use crate::output::Chunk;
+use crate::segments::SegmentKind;
+use crate::utility::*;
+use crate::{AssemblyState, ErrorMsg, rasm_error};
+
+const OBJECT_MAGIC: &[u8] = b"RASMOBJ";
+const OBJECT_VERSION: u8 = 1;
+
+#[derive(Clone, PartialEq)]
+pub enum RelocTarget {
+	Segment(usize),
+	Import(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FixupKind {
+	Full,
+	Lo,
+	Hi,
+}
+
+#[derive(Clone)]
+pub struct Fixup {
+	pub target: RelocTarget,
+	pub kind: FixupKind,
+	pub value: u16,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RelocKind {
+	Word,
+	Lo,
+	Hi,
+	Zeropage,
+}
+
+#[derive(Clone)]
+pub struct Relocation {
+	pub segment: usize,
+	pub offset: u16,
+	pub kind: RelocKind,
+	pub target: RelocTarget,
+	pub value: u16,
+}
+
+#[derive(Clone)]
+pub struct Import {
+	pub name: String,
+	pub zeropage: bool,
+}
+
+pub struct Export {
+	pub name: String,
+	pub segment: Option<usize>,
+	pub value: u16,
+}
+
+pub struct ObjectSegment {
+	pub name: String,
+	pub kind: SegmentKind,
+	pub size: u16,
+	pub data: Vec<u8>,
+}
+
+pub struct ObjectFile {
+	pub name: String,
+	pub segments: Vec<ObjectSegment>,
+	pub relocations: Vec<Relocation>,
+	pub imports: Vec<Import>,
+	pub exports: Vec<Export>,
+}
+
+impl RelocKind {
+	fn to_byte(self) -> u8 {
+		match self {
+			Self::Word => 0,
+			Self::Lo => 1,
+			Self::Hi => 2,
+			Self::Zeropage => 3,
+		}
+	}
+
+	pub fn width(self) -> usize {
+		match self {
+			Self::Word => 2,
+			_ => 1,
+		}
+	}
+
+	fn from_byte(byte: u8, name: &str) -> Self {
+		match byte {
+			0 => Self::Word,
+			1 => Self::Lo,
+			2 => Self::Hi,
+			3 => Self::Zeropage,
+			_ => rasm_error!(0, "Invalid relocation type in object file {}", name),
+		}
+	}
+}
+
+fn kind_to_byte(kind: SegmentKind) -> u8 {
+	match kind {
+		SegmentKind::ReadOnly => 0,
+		SegmentKind::ReadWrite => 1,
+		SegmentKind::Bss => 2,
+		SegmentKind::Zeropage => 3,
+	}
+}
+
+fn kind_from_byte(byte: u8, name: &str) -> SegmentKind {
+	match byte {
+		0 => SegmentKind::ReadOnly,
+		1 => SegmentKind::ReadWrite,
+		2 => SegmentKind::Bss,
+		3 => SegmentKind::Zeropage,
+		_ => rasm_error!(0, "Invalid segment type in object file {}", name),
+	}
+}
+
+pub fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+	bytes.push(lo8(value));
+	bytes.push(hi8(value));
+}
+
+pub fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+	bytes.extend(value.to_le_bytes());
+}
+
+pub fn push_string(bytes: &mut Vec<u8>, string: &str) {
+	if string.len() > u8::MAX as usize {
+		rasm_error!(0, "Symbol name \"{}\" is too long", string);
+	}
+
+	bytes.push(string.len() as u8);
+	bytes.extend(string.bytes());
+}
+
+pub struct Reader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+	name: &'a str,
+}
+
+impl<'a> Reader<'a> {
+	pub fn new(bytes: &'a [u8], name: &'a str) -> Self {
+		Self{bytes, pos: 0, name}
+	}
+
+	pub fn take(&mut self, count: usize) -> &'a [u8] {
+		if self.pos + count > self.bytes.len() {
+			rasm_error!(0, "Unexpected end of file in {}", self.name);
+		}
+
+		let slice = &self.bytes[self.pos..self.pos + count];
+		self.pos += count;
+		slice
+	}
+
+	pub fn u8(&mut self) -> u8 {
+		self.take(1)[0]
+	}
+
+	pub fn u16(&mut self) -> u16 {
+		let bytes = self.take(2);
+		bytes[0] as u16 | (bytes[1] as u16) << 8
+	}
+
+	pub fn u32(&mut self) -> u32 {
+		let bytes = self.take(4);
+		u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+	}
+
+	pub fn string(&mut self) -> String {
+		let len = self.u8() as usize;
+		String::from_utf8_lossy(self.take(len)).into_owned()
+	}
+}
+
+impl ObjectFile {
+	pub fn from_assembly(name: &str, assembly_state: &AssemblyState, chunks: &[Chunk]) -> Self {
+		let config = assembly_state.memory_config.as_ref().unwrap();
+		let segments = config.segments.iter().enumerate().map(|(index, segment)| {
+			let data = chunks.iter().find(|c| c.segment == Some(index)).map_or(vec![], |c| c.bytes.clone());
+			ObjectSegment{name: segment.name.clone(), kind: segment.kind, size: segment.size as u16, data}
+		}).collect();
+
+		let exports = assembly_state.exports.iter().map(|export| {
+			if let Some(value) = assembly_state.constants.get(export) {
+				Export{name: export.clone(), segment: None, value: *value}
+			} else if let Some(value) = assembly_state.labels[&-1].get(export) {
+				let segment = assembly_state.label_segments.get(&(-1, export.clone())).filter(|s| s.1).map(|s| s.0);
+				Export{name: export.clone(), segment, value: *value}
+			} else {
+				rasm_error!(0, "Exported symbol \"{}\" is not defined", export);
+			}
+		}).collect();
+
+		Self{
+			name: name.into(), segments,
+			relocations: assembly_state.relocations.clone(),
+			imports: assembly_state.imports.clone(),
+			exports,
+		}
+	}
+
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut bytes = OBJECT_MAGIC.to_vec();
+		bytes.push(OBJECT_VERSION);
+
+		push_u16(&mut bytes, self.segments.len() as u16);
+		for segment in self.segments.iter() {
+			push_string(&mut bytes, &segment.name);
+			bytes.push(kind_to_byte(segment.kind));
+			push_u16(&mut bytes, segment.size);
+			push_u16(&mut bytes, segment.data.len() as u16);
+			bytes.extend(&segment.data);
+		}
+
+		push_u16(&mut bytes, self.imports.len() as u16);
+		for import in self.imports.iter() {
+			push_string(&mut bytes, &import.name);
+			bytes.push(import.zeropage as u8);
+		}
+
+		push_u16(&mut bytes, self.exports.len() as u16);
+		for export in self.exports.iter() {
+			push_string(&mut bytes, &export.name);
+			push_u16(&mut bytes, export.segment.map_or(u16::MAX, |s| s as u16));
+			push_u16(&mut bytes, export.value);
+		}
+
+		push_u16(&mut bytes, self.relocations.len() as u16);
+		for reloc in self.relocations.iter() {
+			push_u16(&mut bytes, reloc.segment as u16);
+			push_u16(&mut bytes, reloc.offset);
+			bytes.push(reloc.kind.to_byte());
+			match &reloc.target {
+				RelocTarget::Segment(segment) => {
+					bytes.push(0);
+					push_u16(&mut bytes, *segment as u16);
+				},
+				RelocTarget::Import(name) => {
+					bytes.push(1);
+					push_u16(&mut bytes, self.imports.iter().position(|i| &i.name == name).unwrap() as u16);
+				},
+			}
+
+			push_u16(&mut bytes, reloc.value);
+		}
+
+		bytes
+	}
+
+	pub fn from_bytes(bytes: &[u8], name: &str) -> Self {
+		let mut reader = Reader::new(bytes, name);
+		if reader.take(OBJECT_MAGIC.len()) != OBJECT_MAGIC {
+			rasm_error!(0, "{} is not a rasm object file", name);
+		}
+
+		if reader.u8() != OBJECT_VERSION {
+			rasm_error!(0, "Unsupported object file version in {}", name);
+		}
+
+		let segments = (0..reader.u16()).map(|_| {
+			let seg_name = reader.string();
+			let kind = kind_from_byte(reader.u8(), name);
+			let size = reader.u16();
+			let data_len = reader.u16() as usize;
+			ObjectSegment{name: seg_name, kind, size, data: reader.take(data_len).to_vec()}
+		}).collect::<Vec<ObjectSegment>>();
+
+		let imports = (0..reader.u16()).map(|_| {
+			Import{name: reader.string(), zeropage: reader.u8() != 0}
+		}).collect::<Vec<Import>>();
+
+		let exports = (0..reader.u16()).map(|_| {
+			let export_name = reader.string();
+			let segment = match reader.u16() {
+				u16::MAX => None,
+				s if s as usize >= segments.len() => rasm_error!(0, "Invalid segment reference in export \"{}\" in object file {}", export_name, name),
+				s => Some(s as usize),
+			};
+
+			Export{name: export_name, segment, value: reader.u16()}
+		}).collect::<Vec<Export>>();
+
+		let relocations = (0..reader.u16()).map(|_| {
+			let segment = reader.u16() as usize;
+			let offset = reader.u16();
+			let kind = RelocKind::from_byte(reader.u8(), name);
+			match segments.get(segment) {
+				Some(seg) if offset as usize + kind.width() <= seg.data.len() => {},
+				Some(_) => rasm_error!(0, "Relocation at offset ${:04x} is outside its segment in object file {}", offset, name),
+				None => rasm_error!(0, "Invalid segment reference in relocation in object file {}", name),
+			}
+
+			let target = match reader.u8() {
+				0 => match reader.u16() as usize {
+					s if s < segments.len() => RelocTarget::Segment(s),
+					_ => rasm_error!(0, "Invalid segment reference in relocation in object file {}", name),
+				},
+				_ => {
+					let index = reader.u16() as usize;
+					match imports.get(index) {
+						Some(import) => RelocTarget::Import(import.name.clone()),
+						None => rasm_error!(0, "Invalid import reference in object file {}", name),
+					}
+				},
+			};
+
+			Relocation{segment, offset, kind, target, value: reader.u16()}
+		}).collect::<Vec<Relocation>>();
+
+		Self{name: name.into(), segments, relocations, imports, exports}
+	}
+}
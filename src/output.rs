@@ -0,0 +1,238 @@
+use crate::cart::{self, CartType};
+use crate::crunch;
+use crate::disk::{self, DiskFile, DiskFormat, FileKind};
+use crate::hexfile;
+use crate::sourcearray;
+use crate::symbols::Symbol;
+use crate::tape;
+use crate::target::{RegionKind, Target};
+use crate::utility::*;
+use crate::{ErrorMsg, rasm_error, rasm_warning};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+	Prg,
+	Raw,
+	IntelHex,
+	SRecord,
+	CHeader,
+	RustSource,
+	D64,
+	D71,
+	D81,
+	T64,
+	Tap,
+	Crt,
+	O65,
+}
+
+impl OutputFormat {
+	pub fn from_string(string: &str) -> Self {
+		match string.to_lowercase().as_str() {
+			"prg" => Self::Prg,
+			"raw" | "bin" => Self::Raw,
+			"hex" | "ihex" => Self::IntelHex,
+			"srec" | "s19" => Self::SRecord,
+			"c" => Self::CHeader,
+			"rust" | "rs" => Self::RustSource,
+			"d64" => Self::D64,
+			"d71" => Self::D71,
+			"d81" => Self::D81,
+			"t64" => Self::T64,
+			"tap" => Self::Tap,
+			"crt" => Self::Crt,
+			"o65" => Self::O65,
+			_ => rasm_error!(0, "Unknown output format \"{}\"", string),
+		}
+	}
+
+	pub fn extension(&self) -> &'static str {
+		match self {
+			Self::Prg => ".prg",
+			Self::Raw => ".bin",
+			Self::IntelHex => ".hex",
+			Self::SRecord => ".s19",
+			Self::CHeader => ".h",
+			Self::RustSource => ".rs",
+			Self::D64 => ".d64",
+			Self::D71 => ".d71",
+			Self::D81 => ".d81",
+			Self::T64 => ".t64",
+			Self::Tap => ".tap",
+			Self::Crt => ".crt",
+			Self::O65 => ".o65",
+		}
+	}
+
+	pub fn is_cartridge(&self) -> bool {
+		*self == Self::Crt
+	}
+
+	// Formats whose program is loaded into memory by the KERNAL loader
+	pub fn is_loaded(&self) -> bool {
+		matches!(self, Self::Prg | Self::D64 | Self::D71 | Self::D81 | Self::T64 | Self::Tap)
+	}
+
+	pub fn is_relocatable(&self) -> bool {
+		*self == Self::O65
+	}
+}
+
+pub struct OutputOptions {
+	pub pad: Option<usize>,
+	pub fill: u8,
+	pub name: String,
+	pub symbols: Vec<String>,
+	pub disk_name: Option<String>,
+	pub disk_files: Vec<DiskFile>,
+	pub cartridge: CartType,
+	pub crunch: Option<String>,
+}
+
+pub struct Chunk {
+	pub start: u16,
+	pub bytes: Vec<u8>,
+	pub line_num: usize,
+	pub segment: Option<usize>,
+	pub fill: bool,
+	pub bank: Option<usize>,
+}
+
+impl Chunk {
+	pub fn new(start: u16, line_num: usize) -> Self {
+		Self{start, bytes: vec![], line_num, segment: None, fill: false, bank: None}
+	}
+
+	pub fn end(&self) -> usize {
+		self.start as usize + self.bytes.len()
+	}
+
+	pub fn contains(&self, addr: u16) -> bool {
+		(addr as usize) >= self.start as usize && (addr as usize) < self.end()
+	}
+}
+
+pub fn check_overlaps(chunks: &[Chunk]) {
+	let mut sorted = chunks.iter().filter(|c| !c.bytes.is_empty()).collect::<Vec<&Chunk>>();
+	sorted.sort_by_key(|c| (c.bank, c.start));
+
+	for chunk in sorted.iter() {
+		if chunk.end() > 0x10000 {
+			rasm_error!(chunk.line_num, "Code starting at ${:04x} runs past the end of memory", chunk.start);
+		}
+	}
+
+	for pair in sorted.windows(2) {
+		if pair[0].bank == pair[1].bank && pair[0].end() > pair[1].start as usize {
+			let later = if pair[0].line_num > pair[1].line_num { pair[0] } else { pair[1] };
+			rasm_error!(later.line_num, "Bytes at ${:04x}-${:04x} overlap bytes at ${:04x}-${:04x}",
+				pair[0].start, pair[0].end() - 1, pair[1].start, pair[1].end() - 1);
+		}
+	}
+}
+
+pub fn check_regions(chunks: &[Chunk], target: &Target) {
+	for chunk in chunks.iter().filter(|c| !c.bytes.is_empty()) {
+		for region in target.memory_regions() {
+			let start = (chunk.start as usize).max(region.start as usize);
+			let end = chunk.end().min(region.end as usize + 1);
+			if start >= end {
+				continue;
+			}
+
+			match region.kind {
+				RegionKind::Io => {
+					rasm_warning!(chunk.line_num, "Bytes at ${:04x}-${:04x} are written to the {} area", start, end - 1, region.name);
+				},
+				RegionKind::Rom => {
+					rasm_warning!(chunk.line_num, "Bytes at ${:04x}-${:04x} are hidden under {}", start, end - 1, region.name);
+				},
+				RegionKind::Loader => {
+					rasm_warning!(chunk.line_num, "Bytes at ${:04x}-${:04x} overwrite {} memory used by the loader", start, end - 1, region.name.to_lowercase());
+				},
+				RegionKind::Ram | RegionKind::Screen => {},
+			}
+		}
+	}
+}
+
+pub fn flat_image(chunks: &[Chunk], fill: u8) -> (u16, Vec<u8>) {
+	let mut sorted = chunks.iter().filter(|c| !c.bytes.is_empty()).collect::<Vec<&Chunk>>();
+	sorted.sort_by_key(|c| c.start);
+
+	let load_addr = match sorted.first() {
+		Some(chunk) => chunk.start,
+		None => chunks.first().map_or(crate::DEFAULT_ORIGIN, |c| c.start),
+	};
+
+	let mut code = vec![];
+	let mut addr = load_addr as usize;
+	for chunk in sorted {
+		code.resize(code.len() + (chunk.start as usize - addr), fill);
+		code.extend(&chunk.bytes);
+		addr = chunk.end();
+	}
+
+	(load_addr, code)
+}
+
+pub fn prg_image(chunks: &[Chunk], fill: u8) -> Vec<u8> {
+	let (load_addr, image) = flat_image(chunks, fill);
+	let mut code = vec![lo8(load_addr), hi8(load_addr)];
+	code.extend(image);
+	code
+}
+
+pub fn raw_image(chunks: &[Chunk], options: &OutputOptions) -> Vec<u8> {
+	let (_, mut code) = flat_image(chunks, options.fill);
+	if let Some(size) = options.pad {
+		if code.len() > size {
+			rasm_error!(0, "Output is {} bytes, which does not fit in the padded size of {} bytes", code.len(), size);
+		}
+
+		code.resize(size, options.fill);
+	}
+
+	code
+}
+
+fn program_image(chunks: &[Chunk], options: &OutputOptions, symbols: &[Symbol]) -> Vec<u8> {
+	let prg = prg_image(chunks, options.fill);
+	match &options.crunch {
+		Some(entry) => {
+			let addr = symbols.iter().find(|s| &s.scoped_name() == entry).map(|s| s.value).or_else(|| parse_num(entry)).unwrap_or_else(
+				|| rasm_error!(0, "Crunch entry point \"{}\" is not defined", entry)
+			);
+
+			crunch::crunch_prg(&prg, addr)
+		},
+		None => prg,
+	}
+}
+
+fn disk_image(format: DiskFormat, chunks: &[Chunk], options: &OutputOptions, symbols: &[Symbol]) -> Vec<u8> {
+	let program = DiskFile{name: options.name.clone(), kind: FileKind::Prg, data: program_image(chunks, options, symbols)};
+	let mut files = vec![program];
+	files.extend(options.disk_files.iter().map(|f| DiskFile{name: f.name.clone(), kind: f.kind, data: f.data.clone()}));
+
+	let disk_name = options.disk_name.clone().unwrap_or_else(|| options.name.clone());
+	disk::write_disk(format, &disk_name, &files)
+}
+
+pub fn output_image(format: OutputFormat, chunks: &[Chunk], options: &OutputOptions, symbols: &[Symbol]) -> Vec<u8> {
+	match format {
+		OutputFormat::Prg => program_image(chunks, options, symbols),
+		OutputFormat::Raw => raw_image(chunks, options),
+		OutputFormat::IntelHex => hexfile::intel_hex(chunks).into_bytes(),
+		OutputFormat::SRecord => hexfile::s_record(chunks, &options.name).into_bytes(),
+		OutputFormat::CHeader => sourcearray::c_header(chunks, options, symbols).into_bytes(),
+		OutputFormat::RustSource => sourcearray::rust_source(chunks, options, symbols).into_bytes(),
+		OutputFormat::D64 => disk_image(DiskFormat::D64, chunks, options, symbols),
+		OutputFormat::D71 => disk_image(DiskFormat::D71, chunks, options, symbols),
+		OutputFormat::D81 => disk_image(DiskFormat::D81, chunks, options, symbols),
+		OutputFormat::T64 => tape::write_t64(&program_image(chunks, options, symbols), &options.name),
+		OutputFormat::Tap => tape::write_tap(&program_image(chunks, options, symbols), &options.name),
+		OutputFormat::Crt => cart::write_crt(chunks, options.cartridge, &options.name, options.fill),
+		OutputFormat::O65 => unreachable!(),
+	}
+}
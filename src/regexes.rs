@@ -4,12 +4,14 @@ use regex::Regex;
 use crate::instructions::AddressMode;
 use crate::utility::*;
 
-const REGEX_PAT_IMMEDIATE: &'static str = r"^#(.+)$";
-const REGEX_PAT_ABS_X: &'static str = r"^(.+),[Xx]$";
-const REGEX_PAT_ABS_Y: &'static str = r"^(.+),[Yy]$";
-const REGEX_PAT_INDIRECT: &'static str = r"^\((.+)\)$";
-const REGEX_PAT_INDIRECT_X: &'static str = r"^\((.+),[Xx]\)$";
-const REGEX_PAT_INDIRECT_Y: &'static str = r"^\((.+)\),[Yy]$";
+const REGEX_PAT_IMMEDIATE: &str = r"^#(.+)$";
+const REGEX_PAT_ABS_X: &str = r"^(.+),[Xx]$";
+const REGEX_PAT_ABS_Y: &str = r"^(.+),[Yy]$";
+const REGEX_PAT_INDIRECT: &str = r"^\((.+)\)$";
+const REGEX_PAT_INDIRECT_X: &str = r"^\((.+),[Xx]\)$";
+const REGEX_PAT_INDIRECT_Y: &str = r"^\((.+)\),[Yy]$";
+
+pub type AddrFunc = fn(u16, bool) -> (AddressMode, Vec<u8>);
 
 lazy_static! {
 	pub static ref REGEX_ASSIGN: Regex = Regex::new(r"^([\w\*]+)\s*=\s*(.+)$").unwrap();
@@ -17,22 +19,22 @@ lazy_static! {
 	pub static ref REGEX_LABEL: Regex = Regex::new(r"^(\w+):$").unwrap();
 	pub static ref REGEX_PSEUDO: Regex = Regex::new(r"^\.(\w+)(?:\s+(.+))?$").unwrap();
 
-	pub static ref ADDR_REGEXES: Vec<(Regex, fn(u16) -> (AddressMode, Vec<u8>))> = vec![
+	pub static ref ADDR_REGEXES: Vec<(Regex, AddrFunc)> = vec![
 
-		(Regex::new(REGEX_PAT_IMMEDIATE).unwrap(), |op| (AddressMode::Immediate, vec![op as u8])),
-		(Regex::new(REGEX_PAT_INDIRECT).unwrap(), |op| (AddressMode::Indirect, vec![lo8(op), hi8(op)])),
-		(Regex::new(REGEX_PAT_INDIRECT_X).unwrap(), |op| (AddressMode::IndirectX, vec![op as u8])),
-		(Regex::new(REGEX_PAT_INDIRECT_Y).unwrap(), |op| (AddressMode::IndirectY, vec![op as u8])),
-		(Regex::new(REGEX_PAT_ABS_X).unwrap(), |op| {
-			if op > u8::MAX as u16 {
+		(Regex::new(REGEX_PAT_IMMEDIATE).unwrap(), |op, _| (AddressMode::Immediate, vec![op as u8])),
+		(Regex::new(REGEX_PAT_INDIRECT).unwrap(), |op, _| (AddressMode::Indirect, vec![lo8(op), hi8(op)])),
+		(Regex::new(REGEX_PAT_INDIRECT_X).unwrap(), |op, _| (AddressMode::IndirectX, vec![op as u8])),
+		(Regex::new(REGEX_PAT_INDIRECT_Y).unwrap(), |op, _| (AddressMode::IndirectY, vec![op as u8])),
+		(Regex::new(REGEX_PAT_ABS_X).unwrap(), |op, wide| {
+			if op > u8::MAX as u16 || wide {
 				(AddressMode::AbsoluteX, vec![lo8(op), hi8(op)])
 			} else {
 				(AddressMode::ZeropageX, vec![op as u8])
 			}
 		}),
 
-		(Regex::new(REGEX_PAT_ABS_Y).unwrap(), |op| {
-			if op > u8::MAX as u16 {
+		(Regex::new(REGEX_PAT_ABS_Y).unwrap(), |op, wide| {
+			if op > u8::MAX as u16 || wide {
 				(AddressMode::AbsoluteY, vec![lo8(op), hi8(op)])
 			} else {
 				(AddressMode::ZeropageY, vec![op as u8])
@@ -0,0 +1,83 @@
+use std::fmt::Write;
+
+use crate::AssemblyState;
+use crate::output::Chunk;
+use crate::symbols::Symbol;
+
+fn json_string(text: &str) -> String {
+	let mut escaped = String::from("\"");
+	for c in text.chars() {
+		match c {
+			'"' => escaped.push_str("\\\""),
+			'\\' => escaped.push_str("\\\\"),
+			'\n' => escaped.push_str("\\n"),
+			'\r' => escaped.push_str("\\r"),
+			'\t' => escaped.push_str("\\t"),
+			c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+			c => escaped.push(c),
+		}
+	}
+
+	escaped.push('"');
+	escaped
+}
+
+fn json_segment(assembly_state: &AssemblyState, segment: Option<usize>) -> String {
+	match (segment, assembly_state.memory_config.as_ref()) {
+		(Some(index), Some(config)) => json_string(&config.segments[index].name),
+		_ => String::from("null"),
+	}
+}
+
+fn json_list(items: Vec<String>) -> String {
+	if items.is_empty() {
+		String::from("[]")
+	} else {
+		format!("[\n\t\t{}\n\t]", items.join(",\n\t\t"))
+	}
+}
+
+pub fn write_report(source: &str, assembly_state: &AssemblyState, chunks: &[Chunk], symbols: &[Symbol]) -> String {
+	let mut sorted = chunks.iter().filter(|c| !c.bytes.is_empty()).collect::<Vec<&Chunk>>();
+	sorted.sort_by_key(|c| c.start);
+
+	let mut segments = sorted.iter().map(|chunk| {
+		let kind = match (chunk.segment, assembly_state.memory_config.as_ref()) {
+			(Some(index), Some(config)) => json_string(config.segments[index].kind.name()),
+			_ if chunk.fill => json_string("fill"),
+			_ => String::from("null"),
+		};
+
+		format!("{{\"name\": {}, \"kind\": {}, \"start\": {}, \"end\": {}, \"size\": {}, \"initialized\": true}}",
+			json_segment(assembly_state, chunk.segment), kind, chunk.start, chunk.end() - 1, chunk.bytes.len())
+	}).collect::<Vec<String>>();
+
+	if let Some(config) = assembly_state.memory_config.as_ref() {
+		segments.extend(config.segments.iter().filter(|s| s.kind.is_uninitialized() && s.size > 0).map(|segment| {
+			format!("{{\"name\": {}, \"kind\": {}, \"start\": {}, \"end\": {}, \"size\": {}, \"initialized\": false}}",
+				json_string(&segment.name), json_string(segment.kind.name()), segment.base, segment.base as usize + segment.size - 1, segment.size)
+		}));
+	}
+
+	let symbols = symbols.iter().map(|symbol| {
+		let scope = if symbol.block >= 0 { json_string(&format!("block{}", symbol.block)) } else { json_string("global") };
+		let line = symbol.line_num.map_or(String::from("null"), |l| l.to_string());
+		format!("{{\"name\": {}, \"scope\": {}, \"value\": {}, \"kind\": {}, \"line\": {}}}",
+			json_string(&symbol.name), scope, symbol.value, json_string(symbol.kind.name()), line)
+	}).collect::<Vec<String>>();
+
+	let lines = assembly_state.line_ranges.iter().map(|range| {
+		format!("{{\"line\": {}, \"start\": {}, \"end\": {}, \"segment\": {}}}",
+			range.line_num, range.start, range.end, json_segment(assembly_state, range.segment))
+	}).collect::<Vec<String>>();
+
+	let mut report = String::from("{\n");
+	writeln!(report, "\t\"source\": {},", json_string(source)).unwrap();
+	writeln!(report, "\t\"target\": {},", json_string(assembly_state.target.name())).unwrap();
+	writeln!(report, "\t\"cpu\": {},", json_string(assembly_state.target.cpu())).unwrap();
+	writeln!(report, "\t\"segments\": {},", json_list(segments)).unwrap();
+	writeln!(report, "\t\"symbols\": {},", json_list(symbols)).unwrap();
+	writeln!(report, "\t\"lines\": {}", json_list(lines)).unwrap();
+	report.push_str("}\n");
+	report
+}
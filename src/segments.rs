@@ -0,0 +1,253 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::output::Chunk;
+use crate::{ErrorMsg, rasm_error};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SegmentKind {
+	ReadOnly,
+	ReadWrite,
+	Bss,
+	Zeropage,
+}
+
+impl SegmentKind {
+	pub fn from_string(string: &str) -> Self {
+		match string.to_lowercase().as_str() {
+			"ro" => Self::ReadOnly,
+			"rw" => Self::ReadWrite,
+			"bss" => Self::Bss,
+			"zp" => Self::Zeropage,
+			_ => rasm_error!(0, "Invalid segment type \"{}\" in memory configuration", string),
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::ReadOnly => "ro",
+			Self::ReadWrite => "rw",
+			Self::Bss => "bss",
+			Self::Zeropage => "zp",
+		}
+	}
+
+	pub fn is_uninitialized(&self) -> bool {
+		*self == Self::Bss || *self == Self::Zeropage
+	}
+}
+
+pub struct MemoryArea {
+	pub name: String,
+	pub start: u16,
+	pub size: usize,
+	pub fill: bool,
+	pub fillval: u8,
+}
+
+pub struct Segment {
+	pub name: String,
+	pub kind: SegmentKind,
+	pub area: usize,
+	pub fixed_start: Option<u16>,
+
+	pub base: u16,
+	pub pc: usize,
+	pub size: usize,
+}
+
+pub struct MemoryConfig {
+	pub areas: Vec<MemoryArea>,
+	pub segments: Vec<Segment>,
+}
+
+lazy_static! {
+	static ref REGEX_SECTION: Regex = Regex::new(r"(\w+)\s*\{([^}]*)\}").unwrap();
+	static ref REGEX_ENTRY: Regex = Regex::new(r"(\w+)\s*:([^;]*);").unwrap();
+	static ref REGEX_ATTR: Regex = Regex::new(r"^\s*(\w+)\s*=\s*(.+?)\s*$").unwrap();
+}
+
+fn parse_attributes(text: &str) -> Vec<(String, String)> {
+	text.split(',').filter(|a| !a.trim().is_empty()).map(|a| {
+		match REGEX_ATTR.captures(a) {
+			Some(matches) => (matches[1].to_lowercase(), matches[2].to_string()),
+			None => rasm_error!(0, "Invalid attribute \"{}\" in memory configuration", a.trim()),
+		}
+	}).collect()
+}
+
+fn config_num(value: &str) -> usize {
+	let parsed = if let Some(hex) = value.strip_prefix('$') {
+		usize::from_str_radix(hex, 16).ok()
+	} else {
+		value.parse::<usize>().ok()
+	};
+
+	parsed.unwrap_or_else(|| rasm_error!(0, "Invalid number \"{}\" in memory configuration", value))
+}
+
+impl MemoryConfig {
+	pub fn parse(text: &str) -> Self {
+		let stripped = text.lines().map(|l| l.split('#').next().unwrap()).collect::<Vec<&str>>().join("\n");
+		let mut areas = Vec::<MemoryArea>::new();
+		let mut segment_attrs = Vec::<(String, Vec<(String, String)>)>::new();
+
+		for section in REGEX_SECTION.captures_iter(&stripped) {
+			for entry in REGEX_ENTRY.captures_iter(&section[2]) {
+				let name = entry[1].to_string();
+				let attrs = parse_attributes(&entry[2]);
+
+				match section[1].to_uppercase().as_str() {
+					"MEMORY" => {
+						let mut area = MemoryArea{name, start: 0, size: 0, fill: false, fillval: 0};
+						for (key, value) in attrs {
+							match key.as_str() {
+								"start" => area.start = config_num(&value) as u16,
+								"size" => area.size = config_num(&value),
+								"fill" => area.fill = value.to_lowercase() == "yes",
+								"fillval" => area.fillval = config_num(&value) as u8,
+								_ => rasm_error!(0, "Unknown memory area attribute \"{}\"", key),
+							}
+						}
+
+						if area.start as usize + area.size > 0x10000 {
+							rasm_error!(0, "Memory area \"{}\" extends past the end of memory", area.name);
+						}
+
+						areas.push(area);
+					},
+					"SEGMENTS" => {
+						segment_attrs.push((name, attrs));
+					},
+					_ => rasm_error!(0, "Unknown memory configuration section \"{}\"", &section[1]),
+				}
+			}
+		}
+
+		let segments = segment_attrs.into_iter().map(|(name, attrs)| {
+			let mut load = None;
+			let mut kind = SegmentKind::ReadOnly;
+			let mut fixed_start = None;
+			for (key, value) in attrs {
+				match key.as_str() {
+					"load" => load = Some(value),
+					"type" => kind = SegmentKind::from_string(&value),
+					"start" => fixed_start = Some(config_num(&value) as u16),
+					_ => rasm_error!(0, "Unknown segment attribute \"{}\"", key),
+				}
+			}
+
+			let load = load.unwrap_or_else(|| rasm_error!(0, "Segment \"{}\" has no load area", name));
+			let area = areas.iter().position(|a| a.name == load).unwrap_or_else(
+				|| rasm_error!(0, "Segment \"{}\" is loaded into undefined memory area \"{}\"", name, load)
+			);
+
+			let base = fixed_start.unwrap_or(areas[area].start);
+			Segment{name, kind, area, fixed_start, base, pc: base as usize, size: 0}
+		}).collect();
+
+		Self{areas, segments}
+	}
+
+	pub fn make_relocatable(&mut self) {
+		for segment in self.segments.iter_mut() {
+			segment.base = 0;
+			segment.fixed_start = None;
+		}
+	}
+
+	pub fn reset(&mut self) {
+		for segment in self.segments.iter_mut() {
+			segment.pc = segment.base as usize;
+		}
+	}
+
+	pub fn record_sizes(&mut self) {
+		for segment in self.segments.iter_mut() {
+			segment.size = segment.pc - segment.base as usize;
+		}
+	}
+
+	pub fn layout(&mut self) {
+		for (index, area) in self.areas.iter().enumerate() {
+			let mut offset = 0usize;
+			for segment in self.segments.iter_mut().filter(|s| s.area == index) {
+				if let Some(start) = segment.fixed_start {
+					if (start as usize) < area.start as usize + offset {
+						rasm_error!(0, "Segment \"{}\" at ${:04x} overlaps the previous segment in memory area \"{}\"", segment.name, start, area.name);
+					}
+
+					offset = start as usize - area.start as usize;
+				}
+
+				segment.base = (area.start as usize + offset) as u16;
+				offset += segment.size;
+				if offset > area.size {
+					rasm_error!(0, "Segment \"{}\" overflows memory area \"{}\" by {} bytes", segment.name, area.name, offset - area.size);
+				}
+			}
+		}
+	}
+
+	pub fn apply_fill(&self, chunks: &mut Vec<Chunk>) {
+		for area in self.areas.iter().filter(|a| a.fill) {
+			let area_end = area.start as usize + area.size;
+			let mut used = chunks.iter()
+				.filter(|c| !c.bytes.is_empty() && c.end() > area.start as usize && (c.start as usize) < area_end)
+				.map(|c| (c.start as usize, c.end()))
+				.collect::<Vec<(usize, usize)>>();
+			used.sort();
+
+			let mut addr = area.start as usize;
+			let mut fills = vec![];
+			for (start, end) in used.into_iter().chain(std::iter::once((area_end, area_end))) {
+				if start > addr {
+					let mut chunk = Chunk::new(addr as u16, 0);
+					chunk.bytes = vec![area.fillval; start - addr];
+					chunk.fill = true;
+					fills.push(chunk);
+				}
+
+				addr = addr.max(end);
+			}
+
+			chunks.extend(fills);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CONFIG: &str = "MEMORY {\n\tMAIN: start = $1000, size = $0010;\n}\n\nSEGMENTS {\n\tCODE: load = MAIN, type = ro;\n\tDATA: load = MAIN, type = rw;\n\tVECTORS: load = MAIN, type = ro, start = $100c;\n}\n";
+
+	fn config(sizes: [usize; 3]) -> MemoryConfig {
+		let mut config = MemoryConfig::parse(CONFIG);
+		for (segment, size) in config.segments.iter_mut().zip(sizes) {
+			segment.size = size;
+		}
+
+		config
+	}
+
+	#[test]
+	fn layout_places_segments_in_order() {
+		let mut config = config([6, 4, 4]);
+		config.layout();
+		let bases = config.segments.iter().map(|s| s.base).collect::<Vec<u16>>();
+		assert_eq!(bases, vec![0x1000, 0x1006, 0x100c]);
+	}
+
+	#[test]
+	#[should_panic]
+	fn layout_rejects_overflow() {
+		config([6, 4, 5]).layout();
+	}
+
+	#[test]
+	#[should_panic]
+	fn layout_rejects_overlap_with_fixed_start() {
+		config([10, 4, 4]).layout();
+	}
+}
@@ -0,0 +1,69 @@
+use std::fmt::Write;
+
+use crate::output::{Chunk, OutputOptions, flat_image};
+use crate::symbols::Symbol;
+use crate::{ErrorMsg, rasm_error};
+
+const BYTES_PER_ROW: usize = 16;
+
+fn identifier(name: &str) -> String {
+	let mut ident = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect::<String>();
+	if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+		ident.insert(0, '_');
+	}
+
+	ident
+}
+
+fn selected_symbols<'a>(options: &OutputOptions, symbols: &'a [Symbol]) -> Vec<&'a Symbol> {
+	options.symbols.iter().map(|name| {
+		symbols.iter().find(|s| &s.scoped_name() == name).unwrap_or_else(
+			|| rasm_error!(0, "Symbol \"{}\" selected for output is not defined", name)
+		)
+	}).collect()
+}
+
+fn byte_rows(image: &[u8]) -> String {
+	let mut rows = String::new();
+	for row in image.chunks(BYTES_PER_ROW) {
+		let bytes = row.iter().map(|b| format!("0x{:02x}", b)).collect::<Vec<String>>().join(", ");
+		writeln!(rows, "\t{},", bytes).unwrap();
+	}
+
+	rows
+}
+
+pub fn c_header(chunks: &[Chunk], options: &OutputOptions, symbols: &[Symbol]) -> String {
+	let (load_addr, image) = flat_image(chunks, options.fill);
+	let name = identifier(&options.name).to_lowercase();
+	let guard = format!("{}_H", name.to_uppercase());
+
+	let mut text = String::new();
+	writeln!(text, "#ifndef {}\n#define {}\n", guard, guard).unwrap();
+	writeln!(text, "#define {}_LOAD_ADDR 0x{:04x}", name.to_uppercase(), load_addr).unwrap();
+	writeln!(text, "#define {}_SIZE {}", name.to_uppercase(), image.len()).unwrap();
+	for symbol in selected_symbols(options, symbols) {
+		writeln!(text, "#define {}_{} 0x{:04x}", name.to_uppercase(), identifier(&symbol.scoped_name()).to_uppercase(), symbol.value).unwrap();
+	}
+
+	writeln!(text, "\nstatic const unsigned char {}[{}] = {{", name, image.len()).unwrap();
+	text.push_str(&byte_rows(&image));
+	writeln!(text, "}};\n\n#endif").unwrap();
+	text
+}
+
+pub fn rust_source(chunks: &[Chunk], options: &OutputOptions, symbols: &[Symbol]) -> String {
+	let (load_addr, image) = flat_image(chunks, options.fill);
+	let name = identifier(&options.name).to_uppercase();
+
+	let mut text = String::new();
+	writeln!(text, "pub const {}_LOAD_ADDR: u16 = 0x{:04x};", name, load_addr).unwrap();
+	for symbol in selected_symbols(options, symbols) {
+		writeln!(text, "pub const {}_{}: u16 = 0x{:04x};", name, identifier(&symbol.scoped_name()).to_uppercase(), symbol.value).unwrap();
+	}
+
+	writeln!(text, "\npub const {}: [u8; {}] = [", name, image.len()).unwrap();
+	text.push_str(&byte_rows(&image));
+	writeln!(text, "];").unwrap();
+	text
+}
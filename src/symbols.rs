@@ -0,0 +1,58 @@
+use crate::AssemblyState;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+	Label,
+	Constant,
+	Variable,
+}
+
+impl SymbolKind {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::Label => "label",
+			Self::Constant => "constant",
+			Self::Variable => "variable",
+		}
+	}
+}
+
+pub struct Symbol {
+	pub name: String,
+	pub block: isize,
+	pub value: u16,
+	pub kind: SymbolKind,
+	pub line_num: Option<usize>,
+}
+
+impl Symbol {
+	pub fn scoped_name(&self) -> String {
+		if self.block >= 0 {
+			format!("block{}.{}", self.block, self.name)
+		} else {
+			self.name.clone()
+		}
+	}
+}
+
+pub fn collect_symbols(assembly_state: &AssemblyState) -> Vec<Symbol> {
+	let mut symbols = assembly_state.constants.iter().map(|(name, value)| {
+		let kind = if assembly_state.allocator.variables.iter().any(|v| &v.name == name) {
+			SymbolKind::Variable
+		} else {
+			SymbolKind::Constant
+		};
+
+		Symbol{name: name.clone(), block: -1, value: *value, kind, line_num: None}
+	}).collect::<Vec<Symbol>>();
+
+	for (block, labels) in assembly_state.labels.iter() {
+		symbols.extend(labels.iter().map(|(name, value)| {
+			let line_num = assembly_state.label_lines.get(&(*block, name.clone())).map(|l| l.0);
+			Symbol{name: name.clone(), block: *block, value: *value, kind: SymbolKind::Label, line_num}
+		}));
+	}
+
+	symbols.sort_by(|a, b| (a.value, a.block, &a.name).cmp(&(b.value, b.block, &b.name)));
+	symbols
+}
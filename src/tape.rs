@@ -0,0 +1,118 @@
+use crate::utility::*;
+use crate::{ErrorMsg, rasm_error};
+
+const T64_SIGNATURE: &[u8] = b"C64S tape image file";
+const TAP_SIGNATURE: &[u8] = b"C64-TAPE-RAW";
+
+const SHORT: u8 = 0x30;
+const MEDIUM: u8 = 0x42;
+const LONG: u8 = 0x56;
+
+const HEADER_LEADER: usize = 0x6a00;
+const DATA_LEADER: usize = 0x1a00;
+const REPEAT_GAP: usize = 0x4f;
+const TRAILER: usize = 0x4e;
+const HEADER_SIZE: usize = 192;
+
+fn padded_name(name: &str, length: usize) -> Vec<u8> {
+	let mut bytes = name.to_uppercase().bytes().take(length).collect::<Vec<u8>>();
+	bytes.resize(length, b' ');
+	bytes
+}
+
+fn split_prg(prg: &[u8]) -> (u16, &[u8]) {
+	if prg.len() < 2 {
+		rasm_error!(0, "{}", "Program is too short to be written to tape");
+	}
+
+	let start = prg[0] as u16 | (prg[1] as u16) << 8;
+	if start as usize + prg.len() - 2 > 0x10000 {
+		rasm_error!(0, "{}", "Program runs past the end of memory and cannot be written to tape");
+	}
+
+	(start, &prg[2..])
+}
+
+pub fn write_t64(prg: &[u8], name: &str) -> Vec<u8> {
+	let (start, data) = split_prg(prg);
+	let end = (start as usize + data.len()) as u16;
+
+	let mut image = T64_SIGNATURE.to_vec();
+	image.resize(32, 0);
+	image.extend_from_slice(&[0x00, 0x01, 1, 0, 1, 0, 0, 0]);
+	image.extend(padded_name(name, 24));
+
+	let offset = image.len() as u32 + 32;
+	image.extend_from_slice(&[1, 0x82, lo8(start), hi8(start), lo8(end), hi8(end), 0, 0]);
+	image.extend_from_slice(&offset.to_le_bytes());
+	image.extend_from_slice(&[0, 0, 0, 0]);
+	image.extend(padded_name(name, 16));
+
+	image.extend_from_slice(data);
+	image
+}
+
+struct PulseWriter {
+	pulses: Vec<u8>,
+}
+
+impl PulseWriter {
+	fn leader(&mut self, count: usize) {
+		self.pulses.extend(std::iter::repeat_n(SHORT, count));
+	}
+
+	fn byte(&mut self, byte: u8) {
+		self.pulses.extend_from_slice(&[LONG, MEDIUM]);
+		for bit in 0..8 {
+			self.bit(byte >> bit & 1 == 1);
+		}
+
+		self.bit(byte.count_ones().is_multiple_of(2));
+	}
+
+	fn bit(&mut self, one: bool) {
+		if one {
+			self.pulses.extend_from_slice(&[MEDIUM, SHORT]);
+		} else {
+			self.pulses.extend_from_slice(&[SHORT, MEDIUM]);
+		}
+	}
+
+	fn block(&mut self, data: &[u8], leader: usize) {
+		self.leader(leader);
+		for repeat in [false, true] {
+			let countdown = if repeat { 0x09 } else { 0x89 };
+			for sync in (countdown - 8..=countdown).rev() {
+				self.byte(sync);
+			}
+
+			for byte in data.iter() {
+				self.byte(*byte);
+			}
+
+			self.byte(data.iter().fold(0, |c, b| c ^ b));
+			self.pulses.extend_from_slice(&[LONG, SHORT]);
+			self.leader(if repeat { TRAILER } else { REPEAT_GAP });
+		}
+	}
+}
+
+pub fn write_tap(prg: &[u8], name: &str) -> Vec<u8> {
+	let (start, data) = split_prg(prg);
+	let end = (start as usize + data.len()) as u16;
+	let file_type = if start == crate::DEFAULT_ORIGIN { 1 } else { 3 };
+
+	let mut header = vec![file_type, lo8(start), hi8(start), lo8(end), hi8(end)];
+	header.extend(padded_name(name, 16));
+	header.resize(HEADER_SIZE, b' ');
+
+	let mut writer = PulseWriter{pulses: vec![]};
+	writer.block(&header, HEADER_LEADER);
+	writer.block(data, DATA_LEADER);
+
+	let mut image = TAP_SIGNATURE.to_vec();
+	image.extend_from_slice(&[1, 0, 0, 0]);
+	image.extend_from_slice(&(writer.pulses.len() as u32).to_le_bytes());
+	image.extend(writer.pulses);
+	image
+}
@@ -6,6 +6,22 @@ pub enum Target {
 	C64,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum RegionKind {
+	Ram,
+	Rom,
+	Io,
+	Loader,
+	Screen,
+}
+
+pub struct MemoryRegion {
+	pub name: &'static str,
+	pub kind: RegionKind,
+	pub start: u16,
+	pub end: u16,
+}
+
 impl Target {
 	pub fn from_string(string: &str) -> Self {
 		match string.to_uppercase().as_str() {
@@ -13,6 +29,71 @@ impl Target {
 			_ => Self::C64,
 		}
 	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::C64 => "C64",
+		}
+	}
+
+	pub fn basic_start(&self) -> u16 {
+		match self {
+			Self::C64 => 0x0801,
+		}
+	}
+
+	pub fn cpu(&self) -> &'static str {
+		match self {
+			Self::C64 => "6510",
+		}
+	}
+
+	pub fn memory_regions(&self) -> Vec<MemoryRegion> {
+		match self {
+			Self::C64 => vec![
+				MemoryRegion{name: "Zero page", kind: RegionKind::Loader, start: 0x0000, end: 0x00ff},
+				MemoryRegion{name: "Stack", kind: RegionKind::Loader, start: 0x0100, end: 0x01ff},
+				MemoryRegion{name: "System", kind: RegionKind::Loader, start: 0x0200, end: 0x03ff},
+				MemoryRegion{name: "Screen", kind: RegionKind::Screen, start: 0x0400, end: 0x07ff},
+				MemoryRegion{name: "BASIC RAM", kind: RegionKind::Ram, start: 0x0800, end: 0x9fff},
+				MemoryRegion{name: "BASIC ROM", kind: RegionKind::Rom, start: 0xa000, end: 0xbfff},
+				MemoryRegion{name: "Upper RAM", kind: RegionKind::Ram, start: 0xc000, end: 0xcfff},
+				MemoryRegion{name: "I/O", kind: RegionKind::Io, start: 0xd000, end: 0xdfff},
+				MemoryRegion{name: "KERNAL ROM", kind: RegionKind::Rom, start: 0xe000, end: 0xffff},
+			],
+		}
+	}
+
+	pub fn default_memory_config(&self) -> &'static str {
+		match self {
+			Self::C64 => "\
+				MEMORY {
+					ZP:   start = $0002, size = $008e;
+					MAIN: start = $0801, size = $97ff;
+				}
+
+				SEGMENTS {
+					ZEROPAGE: load = ZP, type = zp;
+					CODE:     load = MAIN, type = ro;
+					RODATA:   load = MAIN, type = ro;
+					DATA:     load = MAIN, type = rw;
+					BSS:      load = MAIN, type = bss;
+				}
+			",
+		}
+	}
+
+	pub fn zeropage_free_ranges(&self) -> Vec<(u16, u16)> {
+		match self {
+			Self::C64 => vec![(0x02, 0x8f), (0xfb, 0xfe)],
+		}
+	}
+
+	pub fn bss_free_ranges(&self) -> Vec<(u16, u16)> {
+		match self {
+			Self::C64 => vec![(0xc000, 0xcfff)],
+		}
+	}
 }
 
 pub fn char_format(chr: u8, target: &Target) -> u8 {
@@ -1,4 +1,5 @@
-use crate::AssemblyState;
+use crate::object::{Fixup, FixupKind, RelocTarget};
+use crate::{AssemblyState, ErrorMsg, Pass, rasm_error};
 
 #[inline(always)]
 pub fn lo8(n: u16) -> u8 {
@@ -17,69 +18,123 @@ enum Operation {
 }
 
 pub fn parse_expression(expression: &str, asm_state: &mut AssemblyState) -> Option<u16> {
-	let (expr, op) = if expression.starts_with('<') {
-		(&expression[1..], Operation::Lo8)
-	} else if expression.starts_with('>') {
-		(&expression[1..], Operation::Hi8)
+	let (expr, op) = if let Some(stripped) = expression.strip_prefix('<') {
+		(stripped, Operation::Lo8)
+	} else if let Some(stripped) = expression.strip_prefix('>') {
+		(stripped, Operation::Hi8)
 	} else {
 		(expression, Operation::None)
 	};
+
+	asm_state.last_fixup = None;
 	
-	let result = if expr.contains('+') {
+	let (result, target) = if expr.contains('+') {
 		let mut split = expr.split('+');
 		let left = split.next().unwrap().trim();
 		let right = split.next().unwrap().trim();
 		let rleft = parse_value(left, asm_state);
+		let tleft = asm_state.value_target.take();
 		let rright = parse_value(right, asm_state);
+		let tright = asm_state.value_target.take();
+
+		let target = match (tleft, tright) {
+			(Some(_), Some(_)) => {
+				rasm_error!(asm_state.line_num, "Cannot add two relocatable symbols in \"{}\"", expr);
+			},
+			(t, None) | (None, t) => t,
+		};
 		
-		if rleft.is_some() && rright.is_some() {
-			Some(rleft.unwrap() + rright.unwrap())
-		} else {
-			None
+		match (rleft, rright) {
+			(Some(l), Some(r)) => (Some(l.wrapping_add(r)), target),
+			_ => (None, target),
 		}
 	} else if expr.contains('-') {
 		let mut split = expr.split('-');
 		let left = split.next().unwrap().trim();
 		let right = split.next().unwrap().trim();
 		let rleft = parse_value(left, asm_state);
+		let tleft = asm_state.value_target.take();
 		let rright = parse_value(right, asm_state);
+		let tright = asm_state.value_target.take();
+
+		let target = match (tleft, tright) {
+			(t, None) => t,
+			(Some(l), Some(r)) if l == r => None,
+			_ => {
+				rasm_error!(asm_state.line_num, "Cannot subtract a relocatable symbol in \"{}\"", expr);
+			},
+		};
 		
-		if rleft.is_some() && rright.is_some() {
-			Some(rleft.unwrap() - rright.unwrap())
-		} else {
-			None
+		match (rleft, rright) {
+			(Some(l), Some(r)) => (Some(l.wrapping_sub(r)), target),
+			_ => (None, target),
 		}
 	} else {
-		parse_value(expr, asm_state)
+		let result = parse_value(expr, asm_state);
+		(result, asm_state.value_target.take())
 	};
 
+	if let (Some(value), Some(target)) = (result, target) {
+		let kind = match op {
+			Operation::None => FixupKind::Full,
+			Operation::Lo8 => FixupKind::Lo,
+			Operation::Hi8 => FixupKind::Hi,
+		};
+
+		asm_state.last_fixup = Some(Fixup{target, kind, value});
+	}
+
 	match op {
 		Operation::None => result,
 		Operation::Lo8 => {
-			match result {
-				Some(r) => Some(lo8(r) as u16),
-				None => None,
-			}
+			result.map(|r| lo8(r) as u16)
 		},
 		Operation::Hi8 => {
-			match result {
-				Some(r) => Some(hi8(r) as u16),
-				None => None,
-			}
+			result.map(|r| hi8(r) as u16)
 		},
 	}
 }
 
 fn parse_value(value: &str, asm_state: &mut AssemblyState) -> Option<u16> {
+	asm_state.value_target = None;
 	let current_labels = &asm_state.labels[&asm_state.current_block];
 	match parse_num(value) {
 		Some(num) => Some(num),
 		None => {
+			if let Some(name) = value.strip_prefix('@') {
+				let load_labels = asm_state.load_labels.get(&asm_state.current_block);
+				let key = (asm_state.current_block, name.to_string());
+				if asm_state.pass == Pass::Label {
+					asm_state.procs.reference(key.clone());
+				}
+
+				let result = load_labels.and_then(|l| l.get(name)).or_else(|| current_labels.get(name)).copied();
+				if asm_state.pass == Pass::Main {
+					asm_state.check_bank_reference(&key);
+				}
+
+				asm_state.value_target = asm_state.label_segments.get(&key).map(|s| RelocTarget::Segment(s.0));
+				return result;
+			}
+
 			let string = value.to_string();
+			if asm_state.pass == Pass::Label {
+				asm_state.procs.reference((asm_state.current_block, string.clone()));
+			}
+
 			if asm_state.constants.contains_key(&string) {
 				Some(asm_state.constants[&string])
 			} else if current_labels.contains_key(&string) {
-				Some(current_labels[&string])
+				let key = (asm_state.current_block, string);
+				if asm_state.pass == Pass::Main {
+					asm_state.check_bank_reference(&key);
+				}
+
+				asm_state.value_target = asm_state.label_segments.get(&key).filter(|s| s.1).map(|s| RelocTarget::Segment(s.0));
+				Some(current_labels[&key.1])
+			} else if asm_state.imports.iter().any(|i| i.name == string) {
+				asm_state.value_target = Some(RelocTarget::Import(string));
+				Some(0)
 			} else {
 				None
 			}
@@ -88,16 +143,26 @@ fn parse_value(value: &str, asm_state: &mut AssemblyState) -> Option<u16> {
 }
 
 pub fn parse_num(num: &str) -> Option<u16> {
-	if num.starts_with('$') {
-		u16::from_str_radix(&num[1..], 16).ok()
-	} else if num.starts_with('%') {
-		u16::from_str_radix(&num[1..], 2).ok()
+	if let Some(hex) = num.strip_prefix('$') {
+		u16::from_str_radix(hex, 16).ok()
+	} else if let Some(bin) = num.strip_prefix('%') {
+		u16::from_str_radix(bin, 2).ok()
 	} else if num.starts_with('"') || num.starts_with('\'') {
 		match &num[1..=1].parse::<char>() {
 			Ok(c) => Some((*c as u8) as u16),
 			Err(_) => None,
 		}
 	} else {
-		u16::from_str_radix(num, 10).ok()
+		num.parse::<u16>().ok()
+	}
+}
+
+pub fn parse_size(num: &str) -> Option<usize> {
+	if let Some(hex) = num.strip_prefix('$') {
+		usize::from_str_radix(hex, 16).ok()
+	} else if let Some(kilobytes) = num.strip_suffix(['k', 'K']) {
+		kilobytes.parse::<usize>().ok().map(|k| k * 1024)
+	} else {
+		num.parse::<usize>().ok()
 	}
 }
@@ -0,0 +1,45 @@
+use std::fmt::Write;
+
+use crate::symbols::Symbol;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BreakKind {
+	Break,
+	Watch,
+}
+
+pub struct Breakpoint {
+	pub kind: BreakKind,
+	pub addr: u16,
+	pub mode: Option<String>,
+	pub segment: Option<usize>,
+}
+
+pub fn vice_label_name(symbol: &Symbol) -> String {
+	if symbol.block >= 0 {
+		format!("block{}_{}", symbol.block, symbol.name)
+	} else {
+		symbol.name.clone()
+	}
+}
+
+pub fn write_vice_labels(symbols: &[Symbol], breakpoints: &[Breakpoint]) -> String {
+	let mut commands = String::new();
+	for symbol in symbols.iter() {
+		writeln!(commands, "al C:{:04x} .{}", symbol.value, vice_label_name(symbol)).unwrap();
+	}
+
+	for point in breakpoints.iter() {
+		let command = match point.kind {
+			BreakKind::Break => "break",
+			BreakKind::Watch => "watch",
+		};
+
+		match &point.mode {
+			Some(mode) => writeln!(commands, "{} {} C:{:04x}", command, mode, point.addr).unwrap(),
+			None => writeln!(commands, "{} C:{:04x}", command, point.addr).unwrap(),
+		}
+	}
+
+	commands
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn work_dir(test: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("rasm-{}-{}", test, std::process::id()));
+	fs::create_dir_all(&dir).unwrap();
+	dir
+}
+
+fn rasm(dir: &PathBuf, args: &[&str]) -> Output {
+	Command::new(env!("CARGO_BIN_EXE_rasm")).current_dir(dir).args(args).output().unwrap()
+}
+
+fn assemble(dir: &PathBuf, source: &str, args: &[&str]) -> Output {
+	fs::write(dir.join("test.asm"), source).unwrap();
+	let mut all = vec!["test.asm"];
+	all.extend_from_slice(args);
+	rasm(dir, &all)
+}
+
+fn assert_success(output: &Output) {
+	assert!(output.status.success(), "rasm failed:\n{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+}
+
+#[test]
+fn object_references_proc() {
+	let dir = work_dir("object-proc");
+	let source = ".export start\nstart:\n\tjsr helper\n\tlda helper\n\trts\n.proc helper\n\trts\n.endproc\n";
+	assert_success(&assemble(&dir, source, &["-c", "-o", "test.o"]));
+	assert_success(&rasm(&dir, &["link", "test.o", "-o", "test.prg"]));
+
+	let prg = fs::read(dir.join("test.prg")).unwrap();
+	assert_eq!(prg, vec![0x01, 0x08, 0x20, 0x08, 0x08, 0xad, 0x08, 0x08, 0x60, 0x60]);
+}
+
+#[test]
+fn keep_proc_at_program_start() {
+	let dir = work_dir("entry-proc");
+	let output = assemble(&dir, "* = $0801\n.proc main\n\trts\n.endproc\n", &["-o", "test.prg"]);
+	assert_success(&output);
+	assert!(!String::from_utf8_lossy(&output.stdout).contains("Removed"));
+	assert_eq!(fs::read(dir.join("test.prg")).unwrap(), vec![0x01, 0x08, 0x60]);
+}
+
+#[test]
+fn drop_unreferenced_procs() {
+	let dir = work_dir("dead-proc");
+	let source = "* = $c000\n\tjsr used\n\trts\n.proc unused\n\tnop\n\trts\n.endproc\n.proc used\n\trts\n.endproc\n.break unused\n";
+	let output = assemble(&dir, source, &["-o", "test.prg", "-vs", "test.vs"]);
+	assert_success(&output);
+	assert!(String::from_utf8_lossy(&output.stdout).contains("unused (2 bytes)"));
+	assert_eq!(fs::read(dir.join("test.prg")).unwrap(), vec![0x00, 0xc0, 0x20, 0x04, 0xc0, 0x60, 0x60]);
+	assert!(!fs::read_to_string(dir.join("test.vs")).unwrap().contains("break"));
+}
+
+#[test]
+fn keep_crunch_entry_proc() {
+	let dir = work_dir("crunch-proc");
+	let source = "* = $1000\n.proc init\n\trts\n.endproc\n.proc start\n\tjsr init\n\trts\n.endproc\n";
+	let output = assemble(&dir, source, &["-o", "test.prg", "-crunch", "start"]);
+	assert_success(&output);
+	assert!(!String::from_utf8_lossy(&output.stdout).contains("Removed"));
+}
+
+const SEGMENT_CONFIG: &str = "MEMORY {\n\tMAIN: start = $0801, size = $1000;\n}\n\nSEGMENTS {\n\tCODE: load = MAIN, type = ro;\n\tDATA: load = MAIN, type = rw;\n}\n";
+
+#[test]
+fn basicupstart_stub() {
+	let dir = work_dir("basicupstart");
+	assert_success(&assemble(&dir, ".basicupstart start\nstart:\n\trts\n", &["-o", "test.prg"]));
+
+	// 10 SYS02062: link to the end marker at $080c, line 10, SYS token, digits, end of line and program
+	let stub = vec![0x01, 0x08, 0x0c, 0x08, 0x0a, 0x00, 0x9e, b'0', b'2', b'0', b'6', b'2', 0x00, 0x00, 0x00, 0x60];
+	assert_eq!(fs::read(dir.join("test.prg")).unwrap(), stub);
+
+	fs::write(dir.join("test.cfg"), SEGMENT_CONFIG).unwrap();
+	assert_success(&assemble(&dir, ".segment \"CODE\"\n.basicupstart start\nstart:\n\trts\n", &["-C", "test.cfg", "-o", "test.prg"]));
+	assert_eq!(fs::read(dir.join("test.prg")).unwrap(), stub);
+
+	let output = assemble(&dir, ".segment \"CODE\"\n.byte 1\n.basicupstart start\nstart:\n\trts\n", &["-C", "test.cfg", "-o", "test.prg"]);
+	assert!(!output.status.success());
+}
+
+#[test]
+fn vice_labels_for_linked_program() {
+	let dir = work_dir("link-vice");
+	let source = ".export start\nstart:\n\trts\n";
+	assert!(!assemble(&dir, source, &["-c", "-o", "test.o", "-vs", "test.vs"]).status.success());
+	assert_success(&assemble(&dir, source, &["-c", "-o", "test.o"]));
+	assert_success(&rasm(&dir, &["link", "test.o", "-o", "test.prg", "-vs", "test.vs"]));
+	assert_eq!(fs::read_to_string(dir.join("test.vs")).unwrap(), "al C:0801 .start\n");
+}
+
+#[test]
+fn region_warnings_only_for_loaded_formats() {
+	let dir = work_dir("regions");
+	let source = "* = $e000\n\tnop\n";
+	for (format, warns) in [("prg", true), ("d64", true), ("raw", false), ("hex", false), ("srec", false)] {
+		let output = assemble(&dir, source, &["-f", format, "-o", "test.out"]);
+		assert_success(&output);
+		let text = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+		assert_eq!(text.contains("KERNAL ROM"), warns, "format {}", format);
+	}
+}